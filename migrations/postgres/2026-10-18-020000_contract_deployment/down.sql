@@ -0,0 +1,3 @@
+ALTER TABLE indexer.did_record DROP COLUMN "deployment";
+
+ALTER TABLE indexer.did_delete_record DROP COLUMN "deployment";
@@ -0,0 +1,3 @@
+ALTER TABLE indexer.did_record ADD COLUMN "deployment" VARCHAR NOT NULL DEFAULT 'default';
+
+ALTER TABLE indexer.did_delete_record ADD COLUMN "deployment" VARCHAR NOT NULL DEFAULT 'default';
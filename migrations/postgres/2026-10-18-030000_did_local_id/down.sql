@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS indexer.record_local_id_idx;
+
+ALTER TABLE indexer.did_record DROP COLUMN "localId";
@@ -0,0 +1,3 @@
+ALTER TABLE indexer.did_record ADD COLUMN "localId" VARCHAR;
+
+CREATE INDEX record_local_id_idx ON indexer.did_record ("localId");
@@ -0,0 +1 @@
+ALTER TABLE indexer.did_record DROP COLUMN "cellData";
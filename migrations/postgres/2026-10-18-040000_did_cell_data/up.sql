@@ -0,0 +1 @@
+ALTER TABLE indexer.did_record ADD COLUMN "cellData" BYTEA;
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS indexer.did_cell (
+    "txHash" VARCHAR NOT NULL,
+    "outIndex" INT NOT NULL,
+    "did" VARCHAR NOT NULL,
+    "ckbAddress" VARCHAR NOT NULL,
+    "deployment" VARCHAR NOT NULL,
+    "cellData" BYTEA NOT NULL,
+    "height" BIGINT NOT NULL,
+    "blockTimestamp" BIGINT NOT NULL,
+    "spentTxHash" VARCHAR,
+    "spentInIndex" INT,
+    "spentHeight" BIGINT,
+    PRIMARY KEY ("txHash", "outIndex")
+);
+
+CREATE INDEX cell_did_idx ON indexer.did_cell ("did");
+
+CREATE INDEX cell_live_idx ON indexer.did_cell ("height") WHERE "spentTxHash" IS NULL;
@@ -0,0 +1 @@
+DROP TABLE indexer.invalid_cell;
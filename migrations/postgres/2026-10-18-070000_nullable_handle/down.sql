@@ -0,0 +1,7 @@
+DELETE FROM indexer.did_record WHERE "handle" IS NULL OR "signingKey" IS NULL;
+ALTER TABLE indexer.did_record ALTER COLUMN "handle" SET NOT NULL;
+ALTER TABLE indexer.did_record ALTER COLUMN "signingKey" SET NOT NULL;
+
+DELETE FROM indexer.did_delete_record WHERE "handle" IS NULL OR "signingKey" IS NULL;
+ALTER TABLE indexer.did_delete_record ALTER COLUMN "handle" SET NOT NULL;
+ALTER TABLE indexer.did_delete_record ALTER COLUMN "signingKey" SET NOT NULL;
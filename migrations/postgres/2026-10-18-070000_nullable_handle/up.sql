@@ -0,0 +1,5 @@
+ALTER TABLE indexer.did_record ALTER COLUMN "handle" DROP NOT NULL;
+ALTER TABLE indexer.did_record ALTER COLUMN "signingKey" DROP NOT NULL;
+
+ALTER TABLE indexer.did_delete_record ALTER COLUMN "handle" DROP NOT NULL;
+ALTER TABLE indexer.did_delete_record ALTER COLUMN "signingKey" DROP NOT NULL;
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS indexer.record_pds_endpoint_idx;
+
+ALTER TABLE indexer.did_record DROP COLUMN "serviceErrors";
+ALTER TABLE indexer.did_record DROP COLUMN "pdsEndpoint";
@@ -0,0 +1,4 @@
+ALTER TABLE indexer.did_record ADD COLUMN "pdsEndpoint" VARCHAR;
+ALTER TABLE indexer.did_record ADD COLUMN "serviceErrors" JSONB NOT NULL DEFAULT '[]';
+
+CREATE INDEX record_pds_endpoint_idx ON indexer.did_record ("pdsEndpoint");
@@ -0,0 +1,2 @@
+ALTER TABLE indexer.did_record DROP COLUMN "documentCid";
+ALTER TABLE indexer.did_record DROP COLUMN "documentCbor";
@@ -0,0 +1,2 @@
+ALTER TABLE indexer.did_record ADD COLUMN "documentCbor" BYTEA;
+ALTER TABLE indexer.did_record ADD COLUMN "documentCid" VARCHAR;
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS indexer.record_document_idx;
+
+ALTER TABLE indexer.did_delete_record ALTER COLUMN "document" TYPE VARCHAR USING "document"::text;
+
+ALTER TABLE indexer.did_record ALTER COLUMN "document" TYPE VARCHAR USING "document"::text;
@@ -0,0 +1,5 @@
+ALTER TABLE indexer.did_record ALTER COLUMN "document" TYPE JSONB USING "document"::jsonb;
+
+ALTER TABLE indexer.did_delete_record ALTER COLUMN "document" TYPE JSONB USING "document"::jsonb;
+
+CREATE INDEX record_document_idx ON indexer.did_record USING GIN ("document");
@@ -0,0 +1,11 @@
+ALTER TABLE indexer.invalid_cell ALTER COLUMN "createdAt" TYPE character varying
+    USING to_char("createdAt" AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS.MS"Z"');
+
+ALTER TABLE indexer.did_delete_record DROP COLUMN "blockTimestamp";
+ALTER TABLE indexer.did_delete_record ALTER COLUMN "deletedAt" TYPE character varying
+    USING to_char("deletedAt" AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS.MS"Z"');
+
+DROP INDEX IF EXISTS indexer.record_created_at_idx;
+ALTER TABLE indexer.did_record DROP COLUMN "blockTimestamp";
+ALTER TABLE indexer.did_record ALTER COLUMN "createdAt" TYPE character varying
+    USING to_char("createdAt" AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS.MS"Z"');
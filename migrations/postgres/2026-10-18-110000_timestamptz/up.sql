@@ -0,0 +1,13 @@
+ALTER TABLE indexer.did_record ALTER COLUMN "createdAt" TYPE TIMESTAMPTZ USING "createdAt"::timestamptz;
+ALTER TABLE indexer.did_record ADD COLUMN "blockTimestamp" BIGINT;
+UPDATE indexer.did_record SET "blockTimestamp" = (EXTRACT(EPOCH FROM "createdAt") * 1000)::BIGINT;
+ALTER TABLE indexer.did_record ALTER COLUMN "blockTimestamp" SET NOT NULL;
+
+CREATE INDEX record_created_at_idx ON indexer.did_record ("createdAt");
+
+ALTER TABLE indexer.did_delete_record ALTER COLUMN "deletedAt" TYPE TIMESTAMPTZ USING "deletedAt"::timestamptz;
+ALTER TABLE indexer.did_delete_record ADD COLUMN "blockTimestamp" BIGINT;
+UPDATE indexer.did_delete_record SET "blockTimestamp" = (EXTRACT(EPOCH FROM "deletedAt") * 1000)::BIGINT;
+ALTER TABLE indexer.did_delete_record ALTER COLUMN "blockTimestamp" SET NOT NULL;
+
+ALTER TABLE indexer.invalid_cell ALTER COLUMN "createdAt" TYPE TIMESTAMPTZ USING "createdAt"::timestamptz;
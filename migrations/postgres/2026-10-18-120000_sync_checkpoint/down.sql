@@ -0,0 +1 @@
+DROP TABLE indexer.sync_checkpoint;
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS indexer.sync_checkpoint (
+    "id" INT PRIMARY KEY,
+    "height" BIGINT NOT NULL,
+    "updatedAt" TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1 @@
+DROP INDEX indexer.record_height_cursor_idx;
@@ -0,0 +1 @@
+DROP INDEX indexer.record_handle_prefix_idx;
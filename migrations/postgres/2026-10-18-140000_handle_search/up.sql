@@ -0,0 +1 @@
+CREATE INDEX record_handle_prefix_idx ON indexer.did_record (LOWER(handle) text_pattern_ops);
@@ -0,0 +1 @@
+DROP TABLE indexer.did_event;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS indexer.did_event (
+    "seq" BIGSERIAL PRIMARY KEY,
+    "did" VARCHAR NOT NULL,
+    "kind" VARCHAR NOT NULL,
+    "handle" VARCHAR,
+    "txHash" VARCHAR NOT NULL,
+    "index" INT NOT NULL,
+    "height" BIGINT NOT NULL,
+    "createdAt" TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX event_did_idx ON indexer.did_event ("did");
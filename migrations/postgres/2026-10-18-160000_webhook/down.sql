@@ -0,0 +1,3 @@
+DROP TABLE indexer.webhook_delivery;
+DROP TABLE indexer.webhook;
+ALTER TABLE indexer.did_event DROP COLUMN "previousHandle";
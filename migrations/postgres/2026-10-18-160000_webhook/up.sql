@@ -0,0 +1,25 @@
+ALTER TABLE indexer.did_event ADD COLUMN "previousHandle" VARCHAR;
+
+CREATE TABLE IF NOT EXISTS indexer.webhook (
+    "id" SERIAL PRIMARY KEY,
+    "url" VARCHAR NOT NULL UNIQUE,
+    "secret" VARCHAR NOT NULL,
+    "createdAt" TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS indexer.webhook_delivery (
+    "id" BIGSERIAL PRIMARY KEY,
+    "webhookId" INT NOT NULL REFERENCES indexer.webhook ("id") ON DELETE CASCADE,
+    "eventSeq" BIGINT NOT NULL,
+    "payload" JSONB NOT NULL,
+    "status" VARCHAR NOT NULL,
+    "attempts" INT NOT NULL,
+    "nextAttemptAt" TIMESTAMPTZ NOT NULL,
+    "lastError" VARCHAR,
+    "createdAt" TIMESTAMPTZ NOT NULL,
+    "deliveredAt" TIMESTAMPTZ
+);
+
+CREATE INDEX delivery_due_idx ON indexer.webhook_delivery ("nextAttemptAt") WHERE "status" = 'pending';
+
+CREATE INDEX delivery_webhook_idx ON indexer.webhook_delivery ("webhookId", "id");
@@ -0,0 +1,15 @@
+DROP INDEX indexer.cell_spent_idx;
+DROP INDEX indexer.delete_record_address_idx;
+DROP INDEX indexer.delete_record_handle_idx;
+DROP INDEX indexer.delete_record_did_idx;
+
+-- Only the latest removal of a did and of a handle fits the old keys.
+DELETE FROM indexer.did_delete_record d
+WHERE EXISTS (
+    SELECT 1 FROM indexer.did_delete_record n
+    WHERE (n.did = d.did OR LOWER(n.handle) = LOWER(d.handle))
+      AND (n.height, n."txHash", n."inIndex") > (d.height, d."txHash", d."inIndex")
+);
+ALTER TABLE indexer.did_delete_record DROP CONSTRAINT did_delete_record_pkey;
+ALTER TABLE indexer.did_delete_record ADD PRIMARY KEY (did);
+CREATE UNIQUE INDEX record_handle_lower_idx2 ON indexer.did_delete_record (LOWER(handle));
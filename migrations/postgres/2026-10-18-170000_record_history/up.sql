@@ -0,0 +1,12 @@
+-- Keep every removal of a did, keyed by the input that spent its cell, so
+-- handles and addresses can be traced over time.
+ALTER TABLE indexer.did_delete_record DROP CONSTRAINT did_delete_record_pkey;
+ALTER TABLE indexer.did_delete_record ADD PRIMARY KEY ("txHash", "inIndex");
+DROP INDEX indexer.record_handle_lower_idx2;
+
+CREATE INDEX delete_record_did_idx ON indexer.did_delete_record (did, height);
+CREATE INDEX delete_record_handle_idx ON indexer.did_delete_record (LOWER(handle));
+CREATE INDEX delete_record_address_idx ON indexer.did_delete_record ("ckbAddress");
+
+-- Finds the cell a removed record was read from.
+CREATE INDEX cell_spent_idx ON indexer.did_cell ("spentTxHash", "spentInIndex");
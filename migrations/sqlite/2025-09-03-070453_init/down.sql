@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE did_record;
+DROP TABLE did_delete_record;
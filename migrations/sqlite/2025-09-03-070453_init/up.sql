@@ -0,0 +1,31 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS did_record (
+    "did" VARCHAR NOT NULL PRIMARY KEY,
+    "ckbAddress" VARCHAR NOT NULL UNIQUE,
+    "handle" VARCHAR NOT NULL,
+    "signingKey" VARCHAR NOT NULL,
+    "txHash" VARCHAR NOT NULL,
+    "outIndex" INT NOT NULL,
+    "document" VARCHAR NOT NULL,
+    "height" BIGINT NOT NULL,
+    "createdAt" VARCHAR NOT NULL,
+    "valid" BOOLEAN NOT NULL
+);
+
+CREATE UNIQUE INDEX record_handle_lower_idx ON did_record (LOWER(handle));
+
+CREATE INDEX record_cursor_idx ON did_record ("txHash", "outIndex");
+
+CREATE TABLE IF NOT EXISTS did_delete_record (
+    "did" VARCHAR NOT NULL PRIMARY KEY,
+    "ckbAddress" VARCHAR NOT NULL,
+    "handle" VARCHAR NOT NULL,
+    "signingKey" VARCHAR NOT NULL,
+    "txHash" VARCHAR NOT NULL,
+    "inIndex" INT NOT NULL,
+    "document" VARCHAR NOT NULL,
+    "height" BIGINT NOT NULL,
+    "deletedAt" VARCHAR NOT NULL
+);
+
+CREATE UNIQUE INDEX record_handle_lower_idx2 ON did_delete_record (LOWER(handle));
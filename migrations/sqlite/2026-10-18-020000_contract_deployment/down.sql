@@ -0,0 +1,3 @@
+ALTER TABLE did_record DROP COLUMN "deployment";
+
+ALTER TABLE did_delete_record DROP COLUMN "deployment";
@@ -0,0 +1,3 @@
+ALTER TABLE did_record ADD COLUMN "deployment" VARCHAR NOT NULL DEFAULT 'default';
+
+ALTER TABLE did_delete_record ADD COLUMN "deployment" VARCHAR NOT NULL DEFAULT 'default';
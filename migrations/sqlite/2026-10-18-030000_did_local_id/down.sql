@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS record_local_id_idx;
+
+ALTER TABLE did_record DROP COLUMN "localId";
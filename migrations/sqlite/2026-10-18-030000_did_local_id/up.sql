@@ -0,0 +1,3 @@
+ALTER TABLE did_record ADD COLUMN "localId" VARCHAR;
+
+CREATE INDEX record_local_id_idx ON did_record ("localId");
@@ -0,0 +1 @@
+ALTER TABLE did_record DROP COLUMN "cellData";
@@ -0,0 +1 @@
+ALTER TABLE did_record ADD COLUMN "cellData" BLOB;
@@ -0,0 +1 @@
+DROP TABLE invalid_cell;
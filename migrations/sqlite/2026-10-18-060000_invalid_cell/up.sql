@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS invalid_cell (
+    "txHash" VARCHAR NOT NULL,
+    "outIndex" INT NOT NULL,
+    "did" VARCHAR NOT NULL,
+    "category" VARCHAR NOT NULL,
+    "reason" VARCHAR NOT NULL,
+    "height" BIGINT NOT NULL,
+    "createdAt" VARCHAR NOT NULL,
+    PRIMARY KEY ("txHash", "outIndex")
+);
+
+CREATE INDEX invalid_did_idx ON invalid_cell ("did");
+
+CREATE INDEX invalid_height_idx ON invalid_cell ("height");
@@ -0,0 +1,41 @@
+-- SQLite cannot add NOT NULL in place, so both tables are rebuilt.
+CREATE TABLE did_record_new (
+    "did" VARCHAR NOT NULL PRIMARY KEY,
+    "ckbAddress" VARCHAR NOT NULL UNIQUE,
+    "handle" VARCHAR NOT NULL,
+    "signingKey" VARCHAR NOT NULL,
+    "txHash" VARCHAR NOT NULL,
+    "outIndex" INT NOT NULL,
+    "document" VARCHAR NOT NULL,
+    "height" BIGINT NOT NULL,
+    "createdAt" VARCHAR NOT NULL,
+    "valid" BOOLEAN NOT NULL,
+    "deployment" VARCHAR NOT NULL DEFAULT 'default',
+    "localId" VARCHAR,
+    "cellData" BLOB
+);
+INSERT INTO did_record_new SELECT * FROM did_record WHERE "handle" IS NOT NULL AND "signingKey" IS NOT NULL;
+DROP TABLE did_record;
+ALTER TABLE did_record_new RENAME TO did_record;
+
+CREATE UNIQUE INDEX record_handle_lower_idx ON did_record (LOWER(handle));
+CREATE INDEX record_cursor_idx ON did_record ("txHash", "outIndex");
+CREATE INDEX record_local_id_idx ON did_record ("localId");
+
+CREATE TABLE did_delete_record_new (
+    "did" VARCHAR NOT NULL PRIMARY KEY,
+    "ckbAddress" VARCHAR NOT NULL,
+    "handle" VARCHAR NOT NULL,
+    "signingKey" VARCHAR NOT NULL,
+    "txHash" VARCHAR NOT NULL,
+    "inIndex" INT NOT NULL,
+    "document" VARCHAR NOT NULL,
+    "height" BIGINT NOT NULL,
+    "deletedAt" VARCHAR NOT NULL,
+    "deployment" VARCHAR NOT NULL DEFAULT 'default'
+);
+INSERT INTO did_delete_record_new SELECT * FROM did_delete_record WHERE "handle" IS NOT NULL AND "signingKey" IS NOT NULL;
+DROP TABLE did_delete_record;
+ALTER TABLE did_delete_record_new RENAME TO did_delete_record;
+
+CREATE UNIQUE INDEX record_handle_lower_idx2 ON did_delete_record (LOWER(handle));
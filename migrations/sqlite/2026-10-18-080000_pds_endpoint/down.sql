@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS record_pds_endpoint_idx;
+
+ALTER TABLE did_record DROP COLUMN "serviceErrors";
+ALTER TABLE did_record DROP COLUMN "pdsEndpoint";
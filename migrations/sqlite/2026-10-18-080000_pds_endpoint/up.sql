@@ -0,0 +1,5 @@
+ALTER TABLE did_record ADD COLUMN "pdsEndpoint" VARCHAR;
+-- X'0B' is jsonb('[]'): ADD COLUMN only accepts a constant default.
+ALTER TABLE did_record ADD COLUMN "serviceErrors" JSONB NOT NULL DEFAULT X'0B';
+
+CREATE INDEX record_pds_endpoint_idx ON did_record ("pdsEndpoint");
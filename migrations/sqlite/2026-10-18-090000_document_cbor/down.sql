@@ -0,0 +1,2 @@
+ALTER TABLE did_record DROP COLUMN "documentCid";
+ALTER TABLE did_record DROP COLUMN "documentCbor";
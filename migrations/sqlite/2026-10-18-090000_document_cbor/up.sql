@@ -0,0 +1,2 @@
+ALTER TABLE did_record ADD COLUMN "documentCbor" BLOB;
+ALTER TABLE did_record ADD COLUMN "documentCid" VARCHAR;
@@ -0,0 +1,3 @@
+UPDATE did_delete_record SET "document" = json("document");
+
+UPDATE did_record SET "document" = json("document");
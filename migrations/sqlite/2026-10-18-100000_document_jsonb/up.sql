@@ -0,0 +1,5 @@
+-- Column types are only affinities in SQLite, so the documents are converted in place.
+-- There is no GIN index; document filters go through json_each.
+UPDATE did_record SET "document" = jsonb("document");
+
+UPDATE did_delete_record SET "document" = jsonb("document");
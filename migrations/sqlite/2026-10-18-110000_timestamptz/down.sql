@@ -0,0 +1,8 @@
+UPDATE invalid_cell SET "createdAt" = strftime('%Y-%m-%dT%H:%M:%fZ', "createdAt");
+
+ALTER TABLE did_delete_record DROP COLUMN "blockTimestamp";
+UPDATE did_delete_record SET "deletedAt" = strftime('%Y-%m-%dT%H:%M:%fZ', "deletedAt");
+
+DROP INDEX IF EXISTS record_created_at_idx;
+ALTER TABLE did_record DROP COLUMN "blockTimestamp";
+UPDATE did_record SET "createdAt" = strftime('%Y-%m-%dT%H:%M:%fZ', "createdAt");
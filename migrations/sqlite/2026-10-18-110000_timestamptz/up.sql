@@ -0,0 +1,12 @@
+-- Times are stored as UTC text, which sorts chronologically.
+UPDATE did_record SET "createdAt" = strftime('%Y-%m-%d %H:%M:%f+00:00', "createdAt");
+ALTER TABLE did_record ADD COLUMN "blockTimestamp" BIGINT NOT NULL DEFAULT 0;
+UPDATE did_record SET "blockTimestamp" = CAST(ROUND((julianday("createdAt") - 2440587.5) * 86400000) AS BIGINT);
+
+CREATE INDEX record_created_at_idx ON did_record ("createdAt");
+
+UPDATE did_delete_record SET "deletedAt" = strftime('%Y-%m-%d %H:%M:%f+00:00', "deletedAt");
+ALTER TABLE did_delete_record ADD COLUMN "blockTimestamp" BIGINT NOT NULL DEFAULT 0;
+UPDATE did_delete_record SET "blockTimestamp" = CAST(ROUND((julianday("deletedAt") - 2440587.5) * 86400000) AS BIGINT);
+
+UPDATE invalid_cell SET "createdAt" = strftime('%Y-%m-%d %H:%M:%f+00:00', "createdAt");
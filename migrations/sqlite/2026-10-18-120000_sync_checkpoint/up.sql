@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS sync_checkpoint (
+    "id" INT NOT NULL PRIMARY KEY,
+    "height" BIGINT NOT NULL,
+    "updatedAt" VARCHAR NOT NULL
+);
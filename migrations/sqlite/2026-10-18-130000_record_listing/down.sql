@@ -0,0 +1 @@
+DROP INDEX record_height_cursor_idx;
@@ -0,0 +1 @@
+CREATE INDEX record_height_cursor_idx ON did_record ("height", "txHash", "outIndex");
@@ -0,0 +1,3 @@
+-- Prefix search is a range scan on record_handle_lower_idx, which SQLite
+-- compares bytewise. Kept so both backends report the same schema version.
+SELECT 1;
@@ -0,0 +1 @@
+DROP TABLE did_event;
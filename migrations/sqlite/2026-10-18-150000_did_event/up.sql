@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS did_event (
+    "seq" INTEGER PRIMARY KEY AUTOINCREMENT,
+    "did" VARCHAR NOT NULL,
+    "kind" VARCHAR NOT NULL,
+    "handle" VARCHAR,
+    "txHash" VARCHAR NOT NULL,
+    "index" INT NOT NULL,
+    "height" BIGINT NOT NULL,
+    "createdAt" VARCHAR NOT NULL
+);
+
+CREATE INDEX event_did_idx ON did_event ("did");
@@ -0,0 +1,25 @@
+ALTER TABLE did_event ADD COLUMN "previousHandle" VARCHAR;
+
+CREATE TABLE IF NOT EXISTS webhook (
+    "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+    "url" VARCHAR NOT NULL UNIQUE,
+    "secret" VARCHAR NOT NULL,
+    "createdAt" VARCHAR NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS webhook_delivery (
+    "id" INTEGER PRIMARY KEY AUTOINCREMENT,
+    "webhookId" INT NOT NULL REFERENCES webhook ("id") ON DELETE CASCADE,
+    "eventSeq" BIGINT NOT NULL,
+    "payload" JSONB NOT NULL,
+    "status" VARCHAR NOT NULL,
+    "attempts" INT NOT NULL,
+    "nextAttemptAt" VARCHAR NOT NULL,
+    "lastError" VARCHAR,
+    "createdAt" VARCHAR NOT NULL,
+    "deliveredAt" VARCHAR
+);
+
+CREATE INDEX delivery_due_idx ON webhook_delivery ("nextAttemptAt") WHERE "status" = 'pending';
+
+CREATE INDEX delivery_webhook_idx ON webhook_delivery ("webhookId", "id");
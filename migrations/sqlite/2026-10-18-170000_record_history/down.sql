@@ -0,0 +1,29 @@
+DROP INDEX cell_spent_idx;
+
+-- Only the latest removal of a did and of a handle fits the old keys.
+DELETE FROM did_delete_record
+WHERE EXISTS (
+    SELECT 1 FROM did_delete_record n
+    WHERE (n.did = did_delete_record.did OR LOWER(n.handle) = LOWER(did_delete_record.handle))
+      AND (n.height, n."txHash", n."inIndex")
+          > (did_delete_record.height, did_delete_record."txHash", did_delete_record."inIndex")
+);
+
+CREATE TABLE did_delete_record_new (
+    "did" VARCHAR NOT NULL PRIMARY KEY,
+    "ckbAddress" VARCHAR NOT NULL,
+    "handle" VARCHAR,
+    "signingKey" VARCHAR,
+    "txHash" VARCHAR NOT NULL,
+    "inIndex" INT NOT NULL,
+    "document" VARCHAR NOT NULL,
+    "height" BIGINT NOT NULL,
+    "deletedAt" VARCHAR NOT NULL,
+    "deployment" VARCHAR NOT NULL DEFAULT 'default',
+    "blockTimestamp" BIGINT NOT NULL DEFAULT 0
+);
+INSERT INTO did_delete_record_new SELECT * FROM did_delete_record;
+DROP TABLE did_delete_record;
+ALTER TABLE did_delete_record_new RENAME TO did_delete_record;
+
+CREATE UNIQUE INDEX record_handle_lower_idx2 ON did_delete_record (LOWER(handle));
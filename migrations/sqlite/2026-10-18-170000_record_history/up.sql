@@ -0,0 +1,27 @@
+-- Keep every removal of a did, keyed by the input that spent its cell, so
+-- handles and addresses can be traced over time. SQLite cannot change a
+-- primary key in place, so the table is rebuilt.
+CREATE TABLE did_delete_record_new (
+    "did" VARCHAR NOT NULL,
+    "ckbAddress" VARCHAR NOT NULL,
+    "handle" VARCHAR,
+    "signingKey" VARCHAR,
+    "txHash" VARCHAR NOT NULL,
+    "inIndex" INT NOT NULL,
+    "document" VARCHAR NOT NULL,
+    "height" BIGINT NOT NULL,
+    "deletedAt" VARCHAR NOT NULL,
+    "deployment" VARCHAR NOT NULL DEFAULT 'default',
+    "blockTimestamp" BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY ("txHash", "inIndex")
+);
+INSERT INTO did_delete_record_new SELECT * FROM did_delete_record;
+DROP TABLE did_delete_record;
+ALTER TABLE did_delete_record_new RENAME TO did_delete_record;
+
+CREATE INDEX delete_record_did_idx ON did_delete_record (did, height);
+CREATE INDEX delete_record_handle_idx ON did_delete_record (LOWER(handle));
+CREATE INDEX delete_record_address_idx ON did_delete_record ("ckbAddress");
+
+-- Finds the cell a removed record was read from.
+CREATE INDEX cell_spent_idx ON did_cell ("spentTxHash", "spentInIndex");
@@ -0,0 +1,200 @@
+use crate::types::ResolvedDocument;
+use lru::LruCache;
+use std::{
+    borrow::Borrow,
+    fmt::Write,
+    hash::Hash,
+    num::NonZeroUsize,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// LRU map whose entries also expire after a fixed time to live.
+struct TtlLru<K: Hash + Eq, V> {
+    entries: Option<Mutex<LruCache<K, (Instant, V)>>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K: Hash + Eq, V: Clone> TtlLru<K, V> {
+    fn new(size: usize, ttl: Duration) -> Self {
+        TtlLru {
+            entries: NonZeroUsize::new(size).map(|size| Mutex::new(LruCache::new(size))),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let entries = self.entries.as_ref()?;
+        let mut entries = entries.lock().unwrap();
+        let value = match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        };
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    fn put(&self, key: K, value: V) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().put(key, (Instant::now(), value));
+        }
+    }
+
+    fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+    {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().pop(key);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries
+            .as_ref()
+            .map_or(0, |entries| entries.lock().unwrap().len())
+    }
+}
+
+/// Cache of resolved did documents and handles.
+///
+/// The indexer invalidates the entries of every did and handle it writes.
+/// A lookup that raced with an invalidation is not cached: callers take a
+/// [`ResolutionCache::generation`] before querying and pass it back to `put_*`.
+pub struct ResolutionCache {
+    docs: TtlLru<String, ResolvedDocument>,
+    handles: TtlLru<String, String>,
+    generation: AtomicU64,
+}
+
+impl ResolutionCache {
+    /// A `size` of 0 disables the cache.
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        ResolutionCache {
+            docs: TtlLru::new(size, ttl),
+            handles: TtlLru::new(size, ttl),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn get_doc(&self, did: &str) -> Option<ResolvedDocument> {
+        self.docs.get(did)
+    }
+
+    pub fn put_doc(&self, did: String, doc: ResolvedDocument, generation: u64) {
+        if self.generation() == generation {
+            self.docs.put(did, doc);
+        }
+    }
+
+    pub fn get_handle(&self, handle: &str) -> Option<String> {
+        self.handles.get(handle)
+    }
+
+    pub fn put_handle(&self, handle: String, did: String, generation: u64) {
+        if self.generation() == generation {
+            self.handles.put(handle, did);
+        }
+    }
+
+    /// Drop the cached document of `did` and the cached resolution of `handle`.
+    pub fn invalidate(&self, did: &str, handle: Option<&str>) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.docs.remove(did);
+        if let Some(handle) = handle {
+            self.handles.remove(handle);
+        }
+    }
+
+    /// Hit, miss and size counters in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        let caches = [
+            ("doc", &self.docs.hits, &self.docs.misses, self.docs.len()),
+            (
+                "handle",
+                &self.handles.hits,
+                &self.handles.misses,
+                self.handles.len(),
+            ),
+        ];
+        out.push_str("# TYPE web5_indexer_cache_hits_total counter\n");
+        for (name, hits, _, _) in &caches {
+            let _ = writeln!(
+                out,
+                "web5_indexer_cache_hits_total{{cache=\"{name}\"}} {}",
+                hits.load(Ordering::Relaxed)
+            );
+        }
+        out.push_str("# TYPE web5_indexer_cache_misses_total counter\n");
+        for (name, _, misses, _) in &caches {
+            let _ = writeln!(
+                out,
+                "web5_indexer_cache_misses_total{{cache=\"{name}\"}} {}",
+                misses.load(Ordering::Relaxed)
+            );
+        }
+        out.push_str("# TYPE web5_indexer_cache_entries gauge\n");
+        for (name, _, _, len) in &caches {
+            let _ = writeln!(out, "web5_indexer_cache_entries{{cache=\"{name}\"}} {len}");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DID: &str = "did:web5:zxg43tonzxg43tonzxg43tonzxg43ton";
+
+    fn cache() -> ResolutionCache {
+        ResolutionCache::new(16, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn put_racing_an_invalidation_is_dropped() {
+        let cache = cache();
+        // Taken before the lookup, which then read the record the indexer
+        // replaced in the meantime.
+        let generation = cache.generation();
+        cache.invalidate(DID, Some("alice.example.com"));
+        cache.put_handle("alice.example.com".to_string(), DID.to_string(), generation);
+        assert_eq!(cache.get_handle("alice.example.com"), None);
+
+        let generation = cache.generation();
+        cache.put_handle("alice.example.com".to_string(), DID.to_string(), generation);
+        assert_eq!(cache.get_handle("alice.example.com").as_deref(), Some(DID));
+    }
+
+    #[test]
+    fn mixed_case_handle_is_evicted() {
+        let cache = cache();
+        let generation = cache.generation();
+        cache.put_handle("Alice.Example.com".to_string(), DID.to_string(), generation);
+        assert_eq!(cache.get_handle("Alice.Example.com").as_deref(), Some(DID));
+        cache.invalidate(DID, Some("Alice.Example.com"));
+        assert_eq!(cache.get_handle("Alice.Example.com"), None);
+    }
+}
@@ -1,6 +1,7 @@
 // Generated by Molecule 0.9.1
 
 #![allow(dead_code)]
+#![allow(clippy::all)]
 
 use molecule::prelude::*;
 #[derive(Clone)]
@@ -1,64 +1,88 @@
 use crate::{
-    cell_data::{Bytes, DidWeb5Data, DidWeb5DataUnion},
+    cache::ResolutionCache,
+    config::{ContractDeployment, ValidationProfile},
     db::{
-        check_connection, delete_record, insert_record, query_valid_did_doc_by_index,
-        query_valid_index_set,
+        DbConnection, DbPool, backfill_did_cells, check_connection, delete_invalid_cell,
+        delete_record, delete_record_by_index, enqueue_deliveries, insert_did_cell,
+        insert_did_event, insert_invalid_cell, insert_record, interact, notify_event,
+        query_did_events, query_last_event_seq, query_live_cell_set, query_live_cells,
+        query_valid_did_doc_by_index, query_valid_index_set, spend_did_cell,
+        update_sync_checkpoint,
     },
+    decoder::{decode_did_cell, document_cid},
     error::AppError,
-    types::Web5DocumentData,
-    util::{calculate_address, calculate_web5_did, check_did_doc},
+    events::{EventBus, EventKind},
+    models::{DidCell, DidEvent, InvalidCell, NewDidEvent, NewDidRecord},
+    types::DerivedFields,
+    util::{block_time, calculate_address, calculate_web5_did, check_did_doc},
 };
-use ckb_jsonrpc_types::BlockNumber;
+use ckb_jsonrpc_types::{BlockNumber, BlockView};
 use ckb_sdk::{CkbRpcAsyncClient, NetworkType};
 use ckb_types::H256;
-use diesel::PgConnection;
-use molecule::prelude::Entity;
-use std::{collections::HashSet, str::FromStr, time::Duration};
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::time;
 use tokio_util::sync::CancellationToken;
 
-#[derive(Default)]
 pub struct CkbCtx {
-    valid_cells: HashSet<(H256, i32)>,
+    state: Arc<Mutex<IndexState>>,
     pub token: CancellationToken,
 }
 
+/// Outpoints the indexer tracks between blocks, kept behind a mutex so block
+/// indexing can run on the blocking thread pool.
+struct IndexState {
+    valid_cells: HashSet<(H256, i32)>,
+    live_cells: HashSet<(H256, i32)>,
+    profile: ValidationProfile,
+    cache: Arc<ResolutionCache>,
+    events: Arc<EventBus>,
+    /// Cache invalidations and events of the block being indexed, applied
+    /// once its transaction commits.
+    invalidations: Vec<(String, Option<String>)>,
+    published: Vec<DidEvent>,
+    /// Sequence number of the latest event known to this indexer.
+    last_seq: i64,
+}
+
 pub struct RollingResult {
     pub is_sync: bool,
     pub got_block: bool,
 }
 
 impl CkbCtx {
-    pub async fn init(conn: &mut PgConnection, token: CancellationToken) -> Self {
-        loop {
-            if check_connection(conn) {
-                break;
-            } else {
-                info!("Please create indexer schema");
-                time::sleep(Duration::from_secs(3)).await;
-            }
+    pub async fn init(
+        pool: &DbPool,
+        token: CancellationToken,
+        profile: ValidationProfile,
+        cache: Arc<ResolutionCache>,
+        events: Arc<EventBus>,
+    ) -> Result<Self, AppError> {
+        while !interact(pool, |conn| Ok(check_connection(conn))).await? {
+            info!("Please create indexer schema: web5-indexer migrate");
+            time::sleep(Duration::from_secs(3)).await;
         }
-        let mut ctx = CkbCtx {
-            valid_cells: HashSet::new(),
+        let state = interact(pool, move |conn| {
+            IndexState::load(conn, profile, cache, events)
+        })
+        .await?;
+        Ok(CkbCtx {
+            state: Arc::new(Mutex::new(state)),
             token,
-        };
-        let live_cells = query_valid_index_set(conn).unwrap();
-        if let Some(live_cells) = live_cells {
-            info!("Ckb Ctx init. Found {} records", live_cells.len());
-            for (str, idx) in live_cells {
-                ctx.valid_cells.insert((H256::from_str(&str).unwrap(), idx));
-            }
-        }
-        ctx
+        })
     }
 
     pub async fn rolling(
         &mut self,
         query_height: u64,
         client: &CkbRpcAsyncClient,
-        conn: &mut PgConnection,
+        pool: &DbPool,
         network: NetworkType,
-        target_code_hash: H256,
+        deployments: &[ContractDeployment],
         mut is_sync: bool,
     ) -> Result<RollingResult, AppError> {
         trace!("Tracing scanning block #{query_height}");
@@ -68,7 +92,7 @@ impl CkbCtx {
             .map_err(|e| AppError::CkbRpcError(e.to_string()))?
         {
             Some(block) => {
-                if query_height % 100 == 0 {
+                if query_height.is_multiple_of(100) {
                     info!("Scanning block #{query_height}");
                     if !is_sync {
                         let tip_number = client
@@ -81,100 +105,16 @@ impl CkbCtx {
                         }
                     }
                 }
-                let header = block.header.inner;
-                for tx in block.transactions.into_iter() {
-                    for (in_index, input) in tx.inner.inputs.into_iter().enumerate() {
-                        let pre_tx_hash = input.previous_output.tx_hash.clone();
-                        let pre_index = input.previous_output.index.value() as i32;
-                        if self.valid_cells.contains(&(pre_tx_hash.clone(), pre_index)) {
-                            let did_record = match query_valid_did_doc_by_index(
-                                conn,
-                                pre_tx_hash.to_string(),
-                                pre_index,
-                            ) {
-                                Ok(data) => data,
-                                Err(app_error) => {
-                                    error!(
-                                        "query_valid_did_doc_by_index failed: {}",
-                                        app_error.to_string()
-                                    );
-                                    self.token.cancel();
-                                    return Err(app_error);
-                                }
-                            };
-                            let tx_hash = tx.hash.clone();
-                            match delete_record(
-                                conn,
-                                did_record.did,
-                                did_record.handle,
-                                did_record.signing_key,
-                                header.timestamp.value(),
-                                did_record.ckb_address,
-                                tx_hash.to_string(),
-                                in_index as i32,
-                                query_height as i64,
-                                did_record.document,
-                            ) {
-                                Err(app_err) => {
-                                    error!("delete_record failed: {}", app_err.to_string());
-                                    continue;
-                                }
-                                Ok(_) => self.valid_cells.remove(&(pre_tx_hash, pre_index)),
-                            };
-                        }
-                    }
-
-                    for (out_inx, output) in tx.inner.outputs.into_iter().enumerate() {
-                        if let Some(type_script) = output.type_ {
-                            if type_script.code_hash == target_code_hash {
-                                let ckb_addr = calculate_address(&output.lock.into(), network);
-                                let tx_hash = tx.hash.clone();
-                                let args = type_script.args.as_bytes();
-                                info!("Get doc cell args: {}", hex::encode(args));
-                                let didoc = match parse_didoc_cell(
-                                    tx.inner.outputs_data.get(out_inx).unwrap().as_bytes(),
-                                ) {
-                                    Ok(didoc) => didoc,
-                                    Err(app_err) => {
-                                        error!("parse_didoc_cell failed: {}", app_err.to_string());
-                                        continue;
-                                    }
-                                };
-                                info!(
-                                    "Get did document:\n{}",
-                                    serde_json::to_string_pretty(&didoc).unwrap()
-                                );
-                                let (handle, signing_key) = match check_did_doc(&didoc) {
-                                    Ok(handle) => handle,
-                                    Err(app_err) => {
-                                        error!("check_did_doc failed: {}", app_err.to_string());
-                                        continue;
-                                    }
-                                };
-                                match insert_record(
-                                    conn,
-                                    calculate_web5_did(&args[..20]),
-                                    handle,
-                                    signing_key,
-                                    header.timestamp.value(),
-                                    ckb_addr.to_string(),
-                                    tx_hash.to_string(),
-                                    out_inx as i32,
-                                    query_height as i64,
-                                    didoc,
-                                    true,
-                                ) {
-                                    Err(app_err) => {
-                                        error!("insert_record failed: {}", app_err.to_string());
-                                        continue;
-                                    }
-                                    _ => {}
-                                }
-                                self.valid_cells.insert((tx_hash, out_inx as i32));
-                            }
-                        }
-                    }
-                }
+                let state = self.state.clone();
+                let deployments = deployments.to_vec();
+                let token = self.token.clone();
+                interact(pool, move |conn| {
+                    state
+                        .lock()
+                        .map_err(|e| AppError::RunTimeError(e.to_string()))?
+                        .index(conn, block, query_height, network, &deployments, &token)
+                })
+                .await?;
                 true
             }
             None => {
@@ -202,10 +142,701 @@ impl CkbCtx {
     }
 }
 
-fn parse_didoc_cell(cell_data: &[u8]) -> Result<Web5DocumentData, AppError> {
-    let did_data = DidWeb5Data::from_slice(cell_data).unwrap();
-    let DidWeb5DataUnion::DidWeb5DataV1(did_data_v1) = did_data.to_enum();
-    let did_doc: Bytes = did_data_v1.document();
-    serde_ipld_dagcbor::from_slice(&did_doc.raw_data())
-        .map_err(|e| AppError::DagCborError(e.to_string()))
+impl IndexState {
+    fn load(
+        conn: &mut DbConnection,
+        profile: ValidationProfile,
+        cache: Arc<ResolutionCache>,
+        events: Arc<EventBus>,
+    ) -> Result<Self, AppError> {
+        let mut state = IndexState {
+            valid_cells: HashSet::new(),
+            live_cells: HashSet::new(),
+            profile,
+            cache,
+            events,
+            invalidations: Vec::new(),
+            published: Vec::new(),
+            last_seq: query_last_event_seq(conn)?,
+        };
+        state.load_cells(conn)?;
+        Ok(state)
+    }
+
+    /// Index block `height` and move the sync checkpoint to it, in one
+    /// transaction.
+    fn index(
+        &mut self,
+        conn: &mut DbConnection,
+        block: BlockView,
+        height: u64,
+        network: NetworkType,
+        deployments: &[ContractDeployment],
+        token: &CancellationToken,
+    ) -> Result<(), AppError> {
+        let last_seq = self.last_seq;
+        let indexed = conn.index_transaction(|conn| {
+            self.catch_up(conn)?;
+            self.index_block(conn, block, height, network, deployments, token)?;
+            update_sync_checkpoint(conn, height as i64)
+        });
+        match indexed {
+            Ok(()) => {
+                self.flush_effects();
+                Ok(())
+            }
+            Err(app_err) => {
+                // Nothing of the block was stored, so the tracked
+                // outpoints go back to what the database holds.
+                self.invalidations.clear();
+                self.published.clear();
+                self.last_seq = last_seq;
+                self.load_cells(conn)?;
+                Err(app_err)
+            }
+        }
+    }
+
+    fn load_cells(&mut self, conn: &mut DbConnection) -> Result<(), AppError> {
+        let valid_cells = query_valid_index_set(conn)?.unwrap_or_default();
+        let live_cells = query_live_cell_set(conn)?;
+        info!("Ckb Ctx loaded {} records", valid_cells.len());
+        info!("Ckb Ctx loaded {} live did cells", live_cells.len());
+        self.valid_cells = valid_cells
+            .into_iter()
+            .map(|(str, idx)| Ok((parse_tx_hash(&str)?, idx)))
+            .collect::<Result<_, AppError>>()?;
+        self.live_cells = live_cells
+            .into_iter()
+            .map(|(str, idx)| Ok((parse_tx_hash(&str)?, idx)))
+            .collect::<Result<_, AppError>>()?;
+        Ok(())
+    }
+
+    /// Take over what a `reindex` changed since the previous block: its
+    /// events are in the log but unknown here. The tracked outpoints are
+    /// reloaded and the events published with the block.
+    fn catch_up(&mut self, conn: &mut DbConnection) -> Result<(), AppError> {
+        let events = query_did_events(conn, self.last_seq, i64::MAX)?;
+        let Some(last) = events.last() else {
+            return Ok(());
+        };
+        info!("Taking over {} events logged by reindex", events.len());
+        self.last_seq = last.seq;
+        self.load_cells(conn)?;
+        for event in events {
+            if event.previous_handle.is_some() && event.previous_handle != event.handle {
+                self.invalidations
+                    .push((event.did.clone(), event.previous_handle.clone()));
+            }
+            self.invalidations
+                .push((event.did.clone(), event.handle.clone()));
+            self.published.push(event);
+        }
+        Ok(())
+    }
+
+    fn index_block(
+        &mut self,
+        conn: &mut DbConnection,
+        block: BlockView,
+        query_height: u64,
+        network: NetworkType,
+        deployments: &[ContractDeployment],
+        token: &CancellationToken,
+    ) -> Result<(), AppError> {
+        let header = block.header.inner;
+        let created_at = block_time(header.timestamp.value());
+        for tx in block.transactions.into_iter() {
+            // Dids whose record this transaction removed, with their handle and
+            // input index. Those that get a new record are updated, the rest
+            // deactivated.
+            let mut removed: Vec<(String, Option<String>, i32)> = Vec::new();
+            for (in_index, input) in tx.inner.inputs.into_iter().enumerate() {
+                let pre_tx_hash = input.previous_output.tx_hash.clone();
+                let pre_index = input.previous_output.index.value() as i32;
+                // The cell of a valid record is live even when reindex added
+                // its row after the tracked outpoints were loaded.
+                let live = self.live_cells.remove(&(pre_tx_hash.clone(), pre_index));
+                if (live || self.valid_cells.contains(&(pre_tx_hash.clone(), pre_index)))
+                    && let Err(app_err) = conn.transaction(|conn| {
+                        spend_did_cell(
+                            conn,
+                            pre_tx_hash.to_string(),
+                            pre_index,
+                            tx.hash.to_string(),
+                            in_index as i32,
+                            query_height as i64,
+                        )
+                    })
+                {
+                    error!("spend_did_cell failed: {}", app_err.to_string());
+                }
+                if self.valid_cells.contains(&(pre_tx_hash.clone(), pre_index)) {
+                    let did_record = match query_valid_did_doc_by_index(
+                        conn,
+                        pre_tx_hash.to_string(),
+                        pre_index,
+                    ) {
+                        Ok(data) => data,
+                        Err(app_error) => {
+                            error!(
+                                "query_valid_did_doc_by_index failed: {}",
+                                app_error.to_string()
+                            );
+                            token.cancel();
+                            return Err(app_error);
+                        }
+                    };
+                    let tx_hash = tx.hash.clone();
+                    let (did, handle) = (did_record.did.clone(), did_record.handle.clone());
+                    match conn.transaction(|conn| {
+                        delete_record(
+                            conn,
+                            did_record,
+                            header.timestamp.value(),
+                            tx_hash.to_string(),
+                            in_index as i32,
+                            query_height as i64,
+                        )
+                    }) {
+                        Err(app_err) => {
+                            error!("delete_record failed: {}", app_err.to_string());
+                            continue;
+                        }
+                        Ok(_) => {
+                            self.invalidations.push((did.clone(), handle.clone()));
+                            removed.push((did, handle, in_index as i32));
+                            self.valid_cells.remove(&(pre_tx_hash, pre_index))
+                        }
+                    };
+                }
+            }
+
+            for (out_inx, output) in tx.inner.outputs.into_iter().enumerate() {
+                let Some(type_script) = output.type_ else {
+                    continue;
+                };
+                let Some(deployment) = deployments
+                    .iter()
+                    .find(|d| d.matches(&type_script, query_height))
+                else {
+                    continue;
+                };
+                let ckb_addr = calculate_address(&output.lock.into(), network);
+                let tx_hash = tx.hash.clone();
+                let args = type_script.args.as_bytes();
+                info!(
+                    "Get doc cell args: {}, deployment: {}",
+                    hex::encode(args),
+                    deployment.label
+                );
+                if args.len() < 20 {
+                    error!("did cell args too short: {}", hex::encode(args));
+                    continue;
+                }
+                let Some(cell_data) = tx.inner.outputs_data.get(out_inx) else {
+                    error!("did cell data missing: {tx_hash}#{out_inx}");
+                    continue;
+                };
+                let cell = DidCell {
+                    tx_hash: tx_hash.to_string(),
+                    out_index: out_inx as i32,
+                    did: calculate_web5_did(&args[..20]),
+                    ckb_address: ckb_addr.to_string(),
+                    deployment: deployment.label.clone(),
+                    cell_data: cell_data.as_bytes().to_vec(),
+                    height: query_height as i64,
+                    block_timestamp: header.timestamp.value() as i64,
+                    ..Default::default()
+                };
+                if let Err(app_err) = conn.transaction(|conn| insert_did_cell(conn, &cell)) {
+                    error!("insert_did_cell failed: {}", app_err.to_string());
+                    continue;
+                }
+                self.live_cells.insert((tx_hash.clone(), out_inx as i32));
+                let record = match did_record_from_cell(&cell, self.profile) {
+                    Ok(record) => record,
+                    Err(app_err) => {
+                        error!("did cell rejected: {}", app_err.to_string());
+                        record_invalid_cell(conn, &cell, &app_err);
+                        continue;
+                    }
+                };
+                info!("Get did document:\n{}", record.document);
+                match conn.transaction(|conn| insert_record(conn, &record)) {
+                    Ok(true) => {}
+                    // Indexed from this very cell before, nothing changed.
+                    Ok(false) => {
+                        self.valid_cells.insert((tx_hash, out_inx as i32));
+                        continue;
+                    }
+                    Err(app_err) => {
+                        error!("insert_record failed: {}", app_err.to_string());
+                        record_invalid_cell(conn, &cell, &app_err);
+                        continue;
+                    }
+                }
+                self.invalidations
+                    .push((record.did.clone(), record.handle.clone()));
+                let (kind, previous_handle) =
+                    match removed.iter().position(|(did, ..)| *did == record.did) {
+                        Some(pos) => {
+                            let previous_handle = removed.remove(pos).1;
+                            let kind = EventKind::replacement(&previous_handle, &record.handle);
+                            (kind, previous_handle)
+                        }
+                        None => (EventKind::Create, None),
+                    };
+                self.log_event(
+                    conn,
+                    NewDidEvent {
+                        did: record.did,
+                        kind: kind.as_str().to_string(),
+                        handle: record.handle,
+                        tx_hash: tx_hash.to_string(),
+                        index: out_inx as i32,
+                        height: query_height as i64,
+                        created_at,
+                        previous_handle,
+                    },
+                )?;
+                self.valid_cells.insert((tx_hash, out_inx as i32));
+            }
+
+            for (did, handle, in_index) in removed {
+                self.log_event(
+                    conn,
+                    NewDidEvent {
+                        did,
+                        kind: EventKind::Deactivate.as_str().to_string(),
+                        handle,
+                        tx_hash: tx.hash.to_string(),
+                        index: in_index,
+                        height: query_height as i64,
+                        created_at,
+                        previous_handle: None,
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Log `event` and publish it once the block commits.
+    fn log_event(&mut self, conn: &mut DbConnection, event: NewDidEvent) -> Result<(), AppError> {
+        let event = log_event(conn, &event)?;
+        self.last_seq = event.seq;
+        self.published.push(event);
+        Ok(())
+    }
+
+    fn flush_effects(&mut self) {
+        for (did, handle) in self.invalidations.drain(..) {
+            self.cache.invalidate(&did, handle.as_deref());
+        }
+        for event in self.published.drain(..) {
+            self.events.publish(event);
+        }
+    }
+}
+
+/// Decode and validate a stored did cell into the record it produces.
+pub fn did_record_from_cell(
+    cell: &DidCell,
+    profile: ValidationProfile,
+) -> Result<NewDidRecord, AppError> {
+    let decoded = decode_did_cell(&cell.cell_data)?;
+    trace!("Decoded did cell with data version {}", decoded.version);
+    let DerivedFields {
+        handle,
+        signing_key,
+        pds_endpoint,
+        service_errors,
+    } = check_did_doc(&decoded.document, profile)?;
+    Ok(NewDidRecord {
+        did: cell.did.clone(),
+        ckb_address: cell.ckb_address.clone(),
+        handle,
+        signing_key,
+        tx_hash: cell.tx_hash.clone(),
+        out_index: cell.out_index,
+        document: serde_json::to_value(&decoded.document)
+            .map_err(|e| AppError::RunTimeError(e.to_string()))?,
+        height: cell.height,
+        created_at: block_time(cell.block_timestamp as u64),
+        block_timestamp: cell.block_timestamp,
+        valid: true,
+        deployment: cell.deployment.clone(),
+        local_id: decoded.local_id,
+        cell_data: Some(cell.cell_data.clone()),
+        pds_endpoint,
+        service_errors: serde_json::json!(service_errors),
+        document_cid: Some(document_cid(&decoded.document_cbor)),
+        document_cbor: Some(decoded.document_cbor),
+    })
+}
+
+/// Tx hash as stored in the database, hex without `0x`.
+fn parse_tx_hash(tx_hash: &str) -> Result<H256, AppError> {
+    H256::from_str(tx_hash)
+        .map_err(|e| AppError::DbExecuteFailed(format!("stored tx hash {tx_hash}: {e}")))
+}
+
+fn record_invalid_cell(conn: &mut DbConnection, cell: &DidCell, app_err: &AppError) {
+    let invalid_cell = InvalidCell {
+        tx_hash: cell.tx_hash.clone(),
+        out_index: cell.out_index,
+        did: cell.did.clone(),
+        category: app_err.category().to_string(),
+        reason: app_err.to_string(),
+        height: cell.height,
+        created_at: block_time(cell.block_timestamp as u64),
+    };
+    if let Err(app_err) = conn.transaction(|conn| insert_invalid_cell(conn, &invalid_cell)) {
+        error!("insert_invalid_cell failed: {}", app_err.to_string());
+    }
+}
+
+/// Store `event` with its webhook outbox entries and postgres notification,
+/// all part of the surrounding transaction.
+fn log_event(conn: &mut DbConnection, event: &NewDidEvent) -> Result<DidEvent, AppError> {
+    let event = insert_did_event(conn, event)?;
+    enqueue_deliveries(conn, &event)?;
+    notify_event(conn, &event)?;
+    Ok(event)
+}
+
+/// Rebuild the records of all live did cells from their stored cell data,
+/// so that changed validation rules apply without rescanning the chain.
+/// Records it creates, changes or drops are logged as events like the ones of
+/// the block indexer, which takes them over before its next block.
+pub fn reindex(conn: &mut DbConnection, profile: ValidationProfile) -> Result<(), AppError> {
+    conn.index_transaction(|conn| {
+        let (added, missing) = backfill_did_cells(conn)?;
+        if added > 0 {
+            info!("Added the did cells of {added} records indexed before cells were stored");
+        }
+        if missing > 0 {
+            warn!(
+                "{missing} records were indexed before cell data was stored and can't be re-validated"
+            );
+        }
+        let cells = query_live_cells(conn)?;
+        info!("Reindex {} live did cells", cells.len());
+        let (mut valid, mut invalid, mut changed) = (0, 0, 0);
+        for cell in cells {
+            let previous =
+                match query_valid_did_doc_by_index(conn, cell.tx_hash.clone(), cell.out_index) {
+                    Ok(record) => Some(record),
+                    Err(AppError::DidDocNotFound(_)) => None,
+                    Err(app_err) => return Err(app_err),
+                };
+            delete_record_by_index(conn, cell.tx_hash.clone(), cell.out_index)?;
+            delete_invalid_cell(conn, cell.tx_hash.clone(), cell.out_index)?;
+            let record = match did_record_from_cell(&cell, profile) {
+                Ok(record) => match insert_record(conn, &record) {
+                    Ok(_) => Some(record),
+                    Err(app_err) => {
+                        error!("insert_record failed: {}", app_err.to_string());
+                        record_invalid_cell(conn, &cell, &app_err);
+                        None
+                    }
+                },
+                Err(app_err) => {
+                    info!(
+                        "did cell {}#{} rejected: {app_err}",
+                        cell.tx_hash, cell.out_index
+                    );
+                    record_invalid_cell(conn, &cell, &app_err);
+                    None
+                }
+            };
+            match record {
+                Some(_) => valid += 1,
+                None => invalid += 1,
+            }
+            let (kind, handle, previous_handle) = match (previous, record) {
+                (None, Some(record)) => (EventKind::Create, record.handle, None),
+                (Some(previous), None) => (EventKind::Deactivate, previous.handle, None),
+                (Some(previous), Some(record))
+                    if previous.handle != record.handle
+                        || previous.signing_key != record.signing_key
+                        || previous.pds_endpoint != record.pds_endpoint
+                        || previous.service_errors != record.service_errors =>
+                {
+                    let kind = EventKind::replacement(&previous.handle, &record.handle);
+                    (kind, record.handle, previous.handle)
+                }
+                _ => continue,
+            };
+            log_event(
+                conn,
+                &NewDidEvent {
+                    did: cell.did.clone(),
+                    kind: kind.as_str().to_string(),
+                    handle,
+                    tx_hash: cell.tx_hash.clone(),
+                    index: cell.out_index,
+                    height: cell.height,
+                    created_at: block_time(cell.block_timestamp as u64),
+                    previous_handle,
+                },
+            )?;
+            changed += 1;
+        }
+        info!("Reindex finished. valid: {valid}, invalid: {invalid}, changed: {changed}");
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            query_invalid_cells, query_resume_height, query_sync_checkpoint, run_migrations,
+            tests::sqlite_memory,
+        },
+        decoder::tests::encode_did_cell,
+    };
+    use ckb_jsonrpc_types::ScriptHashType;
+    use serde_json::{Value, json};
+
+    const CODE_HASH: &str = "510150477b10d6ab551a509b71265f3164e9fd4137fcb5a4322f49f03092c7c5";
+    const SECP_CODE_HASH: &str =
+        "0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8";
+
+    fn deployments() -> Vec<ContractDeployment> {
+        vec![ContractDeployment {
+            label: "default".to_string(),
+            code_hash: H256::from_str(CODE_HASH).unwrap(),
+            hash_type: Some(ScriptHashType::Type),
+            activation_height: 0,
+            deactivation_height: None,
+        }]
+    }
+
+    fn h256(byte: u8) -> String {
+        format!("0x{}", hex::encode([byte; 32]))
+    }
+
+    fn document(handle: &str) -> Value {
+        json!({
+            "verificationMethods": {"atproto": "did:key:zQ3sh"},
+            "alsoKnownAs": [format!("at://{handle}")],
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.example.com"
+                }
+            }
+        })
+    }
+
+    /// Transaction `hash` spending the `(tx, index)` outpoints of `inputs` and
+    /// creating a did cell with type args `[args; 20]`, and an owner of its
+    /// own, per `(args, document)` of `outputs`.
+    fn tx(hash: u8, inputs: &[(u8, u32)], outputs: &[(u8, Value)]) -> Value {
+        json!({
+            "version": "0x0",
+            "cell_deps": [],
+            "header_deps": [],
+            "inputs": inputs
+                .iter()
+                .map(|(tx, index)| json!({
+                    "since": "0x0",
+                    "previous_output": {"tx_hash": h256(*tx), "index": format!("{index:#x}")}
+                }))
+                .collect::<Vec<_>>(),
+            "outputs": outputs
+                .iter()
+                .map(|(args, _)| json!({
+                    "capacity": "0x1000",
+                    "lock": {
+                        "code_hash": SECP_CODE_HASH,
+                        "hash_type": "type",
+                        "args": format!("0x{}", hex::encode([!*args; 20]))
+                    },
+                    "type": {
+                        "code_hash": format!("0x{CODE_HASH}"),
+                        "hash_type": "type",
+                        "args": format!("0x{}", hex::encode([*args; 20]))
+                    }
+                }))
+                .collect::<Vec<_>>(),
+            "outputs_data": outputs
+                .iter()
+                .map(|(_, document)| format!("0x{}", hex::encode(encode_did_cell(document, None))))
+                .collect::<Vec<_>>(),
+            "witnesses": [],
+            "hash": h256(hash)
+        })
+    }
+
+    fn block(height: u64, transactions: Vec<Value>) -> BlockView {
+        let zero = h256(0);
+        serde_json::from_value(json!({
+            "header": {
+                "version": "0x0", "compact_target": "0x0", "timestamp": "0x18bcfe56800",
+                "number": format!("{height:#x}"), "epoch": "0x0", "parent_hash": zero,
+                "transactions_root": zero, "proposals_hash": zero, "extra_hash": zero,
+                "dao": zero, "nonce": "0x0", "hash": h256(0x99)
+            },
+            "uncles": [],
+            "transactions": transactions,
+            "proposals": []
+        }))
+        .unwrap()
+    }
+
+    /// Did cell created at 100, left alone at 101 and replaced with another
+    /// handle at 102.
+    fn chain(height: u64) -> BlockView {
+        match height {
+            100 => block(
+                height,
+                vec![tx(0x10, &[], &[(0xab, document("alice.example.com"))])],
+            ),
+            102 => block(
+                height,
+                vec![tx(
+                    0x12,
+                    &[(0x10, 0)],
+                    &[(0xab, document("bob.example.com"))],
+                )],
+            ),
+            _ => block(height, Vec::new()),
+        }
+    }
+
+    fn load(conn: &mut DbConnection, profile: ValidationProfile) -> IndexState {
+        let cache = Arc::new(ResolutionCache::new(0, Duration::ZERO));
+        IndexState::load(conn, profile, cache, Arc::default()).unwrap()
+    }
+
+    fn index(state: &mut IndexState, conn: &mut DbConnection, block: BlockView) {
+        let height = block.header.inner.number.value();
+        let token = CancellationToken::new();
+        state
+            .index(
+                conn,
+                block,
+                height,
+                NetworkType::Testnet,
+                &deployments(),
+                &token,
+            )
+            .unwrap();
+    }
+
+    fn event_kinds(conn: &mut DbConnection) -> Vec<String> {
+        let events = query_did_events(conn, 0, 100).unwrap();
+        events.into_iter().map(|event| event.kind).collect()
+    }
+
+    #[test]
+    fn restarts_resume_after_the_checkpoint() {
+        let mut conn = sqlite_memory();
+        // Every run indexes one more block after resuming where the last stopped.
+        for tip in 100..=102 {
+            let mut state = load(&mut conn, ValidationProfile::Atproto);
+            let from = match query_resume_height(&mut conn) {
+                Ok(height) => height as u64,
+                Err(AppError::CountNotFound) => 100,
+                Err(app_err) => panic!("{app_err}"),
+            };
+            for height in from..=tip {
+                index(&mut state, &mut conn, chain(height));
+            }
+        }
+        assert_eq!(query_resume_height(&mut conn).unwrap(), 103);
+        assert!(query_invalid_cells(&mut conn, 0, 100).unwrap().is_empty());
+        assert_eq!(event_kinds(&mut conn), ["create", "handle_change"]);
+    }
+
+    #[test]
+    fn rescanned_block_changes_nothing() {
+        let mut conn = sqlite_memory();
+        // As when resuming a database indexed before the checkpoint was stored.
+        for _ in 0..2 {
+            let mut state = load(&mut conn, ValidationProfile::Atproto);
+            index(&mut state, &mut conn, chain(100));
+        }
+        assert!(query_invalid_cells(&mut conn, 0, 100).unwrap().is_empty());
+        assert_eq!(event_kinds(&mut conn), ["create"]);
+        assert_eq!(query_live_cells(&mut conn).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reindex_rebuilds_records_for_the_profile() {
+        let mut conn = sqlite_memory();
+        let generic = json!({"verificationMethods": {}, "alsoKnownAs": [], "services": {}});
+        let cells = vec![tx(
+            0x10,
+            &[],
+            &[(0xab, document("alice.example.com")), (0xcd, generic)],
+        )];
+        let mut state = load(&mut conn, ValidationProfile::Generic);
+        index(&mut state, &mut conn, block(100, cells));
+        let tx_hash = h256(0x10)[2..].to_string();
+        let rows = |conn: &mut DbConnection| {
+            let valid: Vec<i32> = (0..2)
+                .filter(|index| query_valid_did_doc_by_index(conn, tx_hash.clone(), *index).is_ok())
+                .collect();
+            let invalid: Vec<(String, i32)> = query_invalid_cells(conn, 0, 100)
+                .unwrap()
+                .into_iter()
+                .map(|cell| (cell.tx_hash, cell.out_index))
+                .collect();
+            (valid, invalid)
+        };
+        assert_eq!(rows(&mut conn), (vec![0, 1], vec![]));
+
+        // The generic document lacks what atproto requires.
+        reindex(&mut conn, ValidationProfile::Atproto).unwrap();
+        assert_eq!(rows(&mut conn), (vec![0], vec![(tx_hash.clone(), 1)]));
+        assert_eq!(event_kinds(&mut conn), ["create", "create", "deactivate"]);
+
+        reindex(&mut conn, ValidationProfile::Generic).unwrap();
+        assert_eq!(rows(&mut conn), (vec![0, 1], vec![]));
+        let kinds = ["create", "create", "deactivate", "create"];
+        assert_eq!(event_kinds(&mut conn), kinds);
+
+        // Unchanged records log nothing.
+        reindex(&mut conn, ValidationProfile::Generic).unwrap();
+        assert_eq!(event_kinds(&mut conn), kinds);
+    }
+
+    #[test]
+    fn index_transactions_wait_for_each_other_on_sqlite() {
+        let path = std::env::temp_dir().join(format!("index-lock-{}.db", std::process::id()));
+        let url = format!("sqlite://{}", path.display());
+        let mut conn = DbConnection::establish(&url).unwrap();
+        run_migrations(&mut conn).unwrap();
+        let (held, release) = (std::sync::Barrier::new(2), std::sync::Barrier::new(2));
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut conn = DbConnection::establish(&url).unwrap();
+                conn.index_transaction(|conn| {
+                    held.wait();
+                    release.wait();
+                    std::thread::sleep(Duration::from_millis(200));
+                    update_sync_checkpoint(conn, 100)
+                })
+                .unwrap();
+            });
+            held.wait();
+            release.wait();
+            // Reads the checkpoint first, which a deferred transaction would
+            // fail to update once the other one committed.
+            conn.index_transaction(|conn| {
+                let height = query_sync_checkpoint(conn)?;
+                update_sync_checkpoint(conn, height.unwrap_or_default() + 1)
+            })
+            .unwrap();
+        });
+        assert_eq!(query_sync_checkpoint(&mut conn).unwrap(), Some(101));
+        std::fs::remove_file(&path).ok();
+    }
 }
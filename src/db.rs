@@ -1,206 +1,1565 @@
+use crate::decoder::decode_did_cell;
 use crate::error::AppError;
 use crate::models;
 use crate::schema::indexer::{
-    did_delete_record::dsl as DidDeleteSchema, did_record::dsl as DidRecordSchema,
+    did_cell::dsl as DidCellSchema, did_delete_record::dsl as DidDeleteSchema,
+    did_event::dsl as EventSchema, did_record::dsl as DidRecordSchema,
+    invalid_cell::dsl as InvalidCellSchema, sync_checkpoint::dsl as CheckpointSchema,
+    webhook::dsl as WebhookSchema, webhook_delivery::dsl as DeliverySchema,
 };
-use crate::types::Web5DocumentData;
-use crate::util::transfer_time;
-use diesel::query_dsl::methods::{FilterDsl, OrderDsl, SelectDsl};
+use crate::sql_types::UtcTimestamp;
+use crate::types::{
+    DidCursor, DidListQuery, DidStatus, HandleMatch, HistoryEntry, HistoryFilter, HistoryPoint,
+    ResolvedDocument,
+};
+use crate::util::block_time;
+use crate::webhook::DeliveryStatus;
+use chrono::{DateTime, TimeDelta, Utc};
+use deadpool::Runtime;
+use deadpool::managed::{Manager, Metrics, Pool, PoolError, RecycleError, RecycleResult};
+use deadpool_sync::SyncWrapper;
+use diesel::connection::{AnsiTransactionManager, SimpleConnection, TransactionManager};
+use diesel::dsl::sql;
+use diesel::sql_types::{BigInt, Bool, Nullable, Text};
 use diesel::{
-    ExpressionMethods, OptionalExtension, RunQueryDsl, SelectableHelper, delete, insert_into,
-    update,
+    BoolExpressionMethods, Connection, ConnectionError, EscapeExpressionMethods, ExpressionMethods,
+    OptionalExtension, QueryDsl, RunQueryDsl, SelectableHelper, TextExpressionMethods,
+    define_sql_function, delete, insert_into, sql_query, update,
 };
-use diesel::{pg::PgConnection, r2d2};
+use diesel::{pg::PgConnection, sqlite::SqliteConnection};
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+use std::time::Duration;
+
+pub const PG_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgres");
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
+
+const SYNC_CHECKPOINT_ID: i32 = 1;
+
+/// Postgres advisory lock held by whoever writes records: the block indexer
+/// for each block and `reindex` for its whole run.
+const INDEX_LOCK_ID: i64 = 0x7765_6235;
+
+/// Postgres channel the indexer notifies of every event.
+pub const NOTIFY_CHANNEL: &str = "indexer_events";
+
+/// Connection to one of the supported storage backends, chosen by the scheme
+/// of `DATABASE_URL`: `sqlite://<path>` opens a SQLite file, anything else is
+/// handed to postgres.
+pub enum DbConnection {
+    Pg(PgConnection),
+    Sqlite(SqliteConnection),
+}
+
+define_sql_function!(fn lower(x: Nullable<Text>) -> Nullable<Text>);
+
+/// Run `$body` with `$c` bound to the backend connection. The body is compiled
+/// once per backend, so the same diesel query serves both.
+macro_rules! with_conn {
+    ($conn:expr, |$c:ident| $body:expr) => {
+        match $conn {
+            DbConnection::Pg($c) => $body,
+            DbConnection::Sqlite($c) => $body,
+        }
+    };
+}
+
+impl DbConnection {
+    pub fn establish(db_url: &str) -> Result<Self, ConnectionError> {
+        match db_url.strip_prefix("sqlite://") {
+            Some(path) => {
+                let mut conn = SqliteConnection::establish(path)?;
+                conn.batch_execute(
+                    "PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 5000; PRAGMA foreign_keys = ON;",
+                )
+                .map_err(ConnectionError::CouldntSetupConfiguration)?;
+                Ok(DbConnection::Sqlite(conn))
+            }
+            None => {
+                let mut conn = PgConnection::establish(db_url)?;
+                // Tables live in the `indexer` schema on postgres and unqualified in SQLite.
+                // `public` stays first so the migration table is the one the diesel CLI created.
+                conn.batch_execute("SET search_path TO public, indexer")
+                    .map_err(ConnectionError::CouldntSetupConfiguration)?;
+                Ok(DbConnection::Pg(conn))
+            }
+        }
+    }
+
+    pub fn backend_name(&self) -> &'static str {
+        match self {
+            DbConnection::Pg(_) => "postgres",
+            DbConnection::Sqlite(_) => "sqlite",
+        }
+    }
+
+    fn ping(&mut self) -> Result<(), AppError> {
+        with_conn!(self, |conn| conn.batch_execute("SELECT 1"))?;
+        Ok(())
+    }
 
-pub type DbPool = r2d2::Pool<r2d2::ConnectionManager<PgConnection>>;
+    fn migrations(&self) -> EmbeddedMigrations {
+        match self {
+            DbConnection::Pg(_) => PG_MIGRATIONS,
+            DbConnection::Sqlite(_) => SQLITE_MIGRATIONS,
+        }
+    }
+
+    /// Run `f` in a transaction, rolled back when it returns an error.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T, AppError>
+    where
+        F: FnOnce(&mut DbConnection) -> Result<T, AppError>,
+    {
+        with_conn!(self, |conn| AnsiTransactionManager::begin_transaction(conn))?;
+        self.finish_transaction(f)
+    }
+
+    /// Run `f` in a transaction holding the index lock, so that a `reindex`
+    /// and the block indexer take turns instead of interleaving their writes.
+    /// On postgres that's an advisory lock. SQLite has a single writer, taken
+    /// up front with `BEGIN IMMEDIATE`: taken by the first write instead, it
+    /// would fail outright once the other writer committed since our reads.
+    pub fn index_transaction<T, F>(&mut self, f: F) -> Result<T, AppError>
+    where
+        F: FnOnce(&mut DbConnection) -> Result<T, AppError>,
+    {
+        let DbConnection::Sqlite(conn) = self else {
+            return self.transaction(|conn| {
+                lock_index(conn)?;
+                f(conn)
+            });
+        };
+        // Each attempt waits out the busy timeout; like the advisory lock,
+        // keep waiting until the other writer is done.
+        loop {
+            match AnsiTransactionManager::begin_transaction_sql(conn, "BEGIN IMMEDIATE") {
+                Ok(()) => break,
+                Err(diesel::result::Error::DatabaseError(_, info))
+                    if info.message().contains("database is locked") =>
+                {
+                    debug!("waiting for the index lock");
+                }
+                Err(e) => return Err(AppError::DbExecuteFailed(e.to_string())),
+            }
+        }
+        self.finish_transaction(f)
+    }
+
+    /// Commit the transaction begun before `f` when it succeeds, roll it back
+    /// otherwise.
+    fn finish_transaction<T, F>(&mut self, f: F) -> Result<T, AppError>
+    where
+        F: FnOnce(&mut DbConnection) -> Result<T, AppError>,
+    {
+        match f(self) {
+            Ok(value) => {
+                with_conn!(self, |conn| AnsiTransactionManager::commit_transaction(
+                    conn
+                ))?;
+                Ok(value)
+            }
+            Err(app_err) => {
+                if let Err(e) =
+                    with_conn!(self, |conn| AnsiTransactionManager::rollback_transaction(
+                        conn
+                    ))
+                {
+                    error!("rollback transaction failed: {e}");
+                }
+                Err(app_err)
+            }
+        }
+    }
+}
+
+pub struct DbManager {
+    db_url: String,
+}
+
+impl Manager for DbManager {
+    type Type = SyncWrapper<DbConnection>;
+    type Error = AppError;
+
+    async fn create(&self) -> Result<Self::Type, AppError> {
+        let db_url = self.db_url.clone();
+        SyncWrapper::new(Runtime::Tokio1, move || {
+            DbConnection::establish(&db_url).map_err(|e| AppError::DbConnectFailed(e.to_string()))
+        })
+        .await
+    }
+
+    async fn recycle(&self, obj: &mut Self::Type, _: &Metrics) -> RecycleResult<AppError> {
+        if obj.is_mutex_poisoned() {
+            return Err(RecycleError::message("connection mutex is poisoned"));
+        }
+        obj.interact(|conn| conn.ping())
+            .await
+            .map_err(|e| RecycleError::message(e.to_string()))?
+            .map_err(RecycleError::Backend)
+    }
+}
+
+pub type DbPool = Pool<DbManager>;
 
 #[tracing::instrument(skip_all)]
-pub fn establish_connection(db_url: String) -> DbPool {
+pub fn establish_connection(
+    db_url: String,
+    pool_size: usize,
+    timeout: Duration,
+) -> Result<DbPool, AppError> {
     info!("Establishing database connection");
-    let manager = r2d2::ConnectionManager::<PgConnection>::new(db_url);
-    r2d2::Pool::builder()
-        .build(manager)
-        .expect("database URL should be valid path to SQLite DB file")
+    Pool::builder(DbManager { db_url })
+        .max_size(pool_size)
+        .wait_timeout(Some(timeout))
+        .create_timeout(Some(timeout))
+        .recycle_timeout(Some(timeout))
+        .runtime(Runtime::Tokio1)
+        .build()
+        .map_err(|e| AppError::DbConnectFailed(e.to_string()))
+}
+
+/// Run `f` with a pooled connection on the blocking thread pool, so that
+/// neither the indexer nor the http workers block the async runtime.
+pub async fn interact<T, F>(pool: &DbPool, f: F) -> Result<T, AppError>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, AppError> + Send + 'static,
+    T: Send + 'static,
+{
+    let conn = pool.get().await.map_err(|e| match e {
+        PoolError::Timeout(_) => AppError::DbPoolTimeout,
+        PoolError::Backend(app_err) => app_err,
+        e => AppError::DbConnectFailed(e.to_string()),
+    })?;
+    conn.interact(f)
+        .await
+        .map_err(|e| AppError::RunTimeError(format!("db task failed: {e}")))?
+}
+
+#[tracing::instrument(skip_all)]
+pub fn run_migrations(conn: &mut DbConnection) -> Result<(), AppError> {
+    let migrations = conn.migrations();
+    let applied = with_conn!(conn, |conn| conn.run_pending_migrations(migrations))
+        .map_err(|e| AppError::DbExecuteFailed(format!("run migrations failed: {e}")))?;
+    for version in applied {
+        info!("Applied migration {version}");
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn schema_version(conn: &mut DbConnection) -> Result<Option<String>, AppError> {
+    let applied = with_conn!(conn, |conn| conn.applied_migrations())
+        .map_err(|e| AppError::DbExecuteFailed(format!("query migrations failed: {e}")))?;
+    Ok(applied.into_iter().max().map(|version| version.to_string()))
+}
+
+#[tracing::instrument(skip_all)]
+pub fn pending_migration_count(conn: &mut DbConnection) -> Result<usize, AppError> {
+    let migrations = conn.migrations();
+    with_conn!(conn, |conn| conn
+        .pending_migrations(migrations)
+        .map(|pending| pending.len()))
+    .map_err(|e| AppError::DbExecuteFailed(format!("query migrations failed: {e}")))
+}
+
+/// Apply the pending migrations when `migrate` is set, then log the schema
+/// version and what is left pending.
+pub fn prepare_schema(conn: &mut DbConnection, migrate: bool) -> Result<(), AppError> {
+    if migrate {
+        run_migrations(conn)?;
+    }
+    let pending = pending_migration_count(conn)?;
+    match schema_version(conn)? {
+        Some(version) => info!(
+            "{} schema version: {version}, pending migrations: {pending}",
+            conn.backend_name()
+        ),
+        None => info!(
+            "{} schema not created, pending migrations: {pending}",
+            conn.backend_name()
+        ),
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub fn check_connection(conn: &mut DbConnection) -> bool {
+    with_conn!(conn, |conn| {
+        DidRecordSchema::did_record
+            .filter(DidRecordSchema::valid.eq(true))
+            .select(models::DidRecord::as_select())
+            .first(conn)
+            .optional()
+            .is_ok()
+    })
+}
+
+/// Height of the last block the indexer finished, as seen by this database.
+#[tracing::instrument(skip_all)]
+pub fn query_sync_checkpoint(conn: &mut DbConnection) -> Result<Option<i64>, AppError> {
+    with_conn!(conn, |conn| {
+        CheckpointSchema::sync_checkpoint
+            .filter(CheckpointSchema::id.eq(SYNC_CHECKPOINT_ID))
+            .select(CheckpointSchema::height)
+            .first(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn update_sync_checkpoint(conn: &mut DbConnection, height: i64) -> Result<(), AppError> {
+    let updated_at = UtcTimestamp(Utc::now());
+    with_conn!(conn, |conn| {
+        insert_into(CheckpointSchema::sync_checkpoint)
+            .values((
+                CheckpointSchema::id.eq(SYNC_CHECKPOINT_ID),
+                CheckpointSchema::height.eq(height),
+                CheckpointSchema::updatedAt.eq(updated_at),
+            ))
+            .on_conflict(CheckpointSchema::id)
+            .do_update()
+            .set((
+                CheckpointSchema::height.eq(height),
+                CheckpointSchema::updatedAt.eq(updated_at),
+            ))
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        Ok(())
+    })
+}
+
+/// Wait for the postgres advisory lock behind [`DbConnection::index_transaction`],
+/// held until the surrounding transaction ends.
+#[tracing::instrument(skip_all)]
+fn lock_index(conn: &mut DbConnection) -> Result<(), AppError> {
+    let DbConnection::Pg(conn) = conn else {
+        return Ok(());
+    };
+    sql_query("SELECT pg_advisory_xact_lock($1)")
+        .bind::<BigInt, _>(INDEX_LOCK_ID)
+        .execute(conn)
+        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+    Ok(())
+}
+
+/// Append `event` to the event log, returning it with its sequence number.
+#[tracing::instrument(skip_all)]
+pub fn insert_did_event(
+    conn: &mut DbConnection,
+    event: &models::NewDidEvent,
+) -> Result<models::DidEvent, AppError> {
+    with_conn!(conn, |conn| {
+        insert_into(EventSchema::did_event)
+            .values(event.clone())
+            .returning(models::DidEvent::as_returning())
+            .get_result(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+/// `NOTIFY` listeners on the shared postgres instance of `event`, with a
+/// compact `{"seq", "did", "handle", "op", "height"}` payload. Sent when the
+/// surrounding transaction commits; nothing to do on SQLite.
+#[tracing::instrument(skip_all)]
+pub fn notify_event(conn: &mut DbConnection, event: &models::DidEvent) -> Result<(), AppError> {
+    let DbConnection::Pg(conn) = conn else {
+        return Ok(());
+    };
+    sql_query("SELECT pg_notify($1, $2)")
+        .bind::<Text, _>(NOTIFY_CHANNEL)
+        .bind::<Text, _>(notify_payload(event).to_string())
+        .execute(conn)
+        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+    Ok(())
+}
+
+fn notify_payload(event: &models::DidEvent) -> serde_json::Value {
+    serde_json::json!({
+        "seq": event.seq,
+        "did": event.did,
+        "handle": event.handle,
+        "op": event.kind,
+        "height": event.height,
+    })
+}
+
+/// Events with a sequence number above `after`, oldest first.
+#[tracing::instrument(skip_all)]
+pub fn query_did_events(
+    conn: &mut DbConnection,
+    after: i64,
+    limit: i64,
+) -> Result<Vec<models::DidEvent>, AppError> {
+    with_conn!(conn, |conn| {
+        EventSchema::did_event
+            .filter(EventSchema::seq.gt(after))
+            .order(EventSchema::seq.asc())
+            .limit(limit)
+            .select(models::DidEvent::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
 }
 
+/// Sequence number of the latest event, 0 when the log is empty.
 #[tracing::instrument(skip_all)]
-pub fn check_connection(conn: &mut PgConnection) -> bool {
-    DidRecordSchema::did_record
-        .filter(DidRecordSchema::valid.eq(true))
-        .select(models::DidRecord::as_select())
-        .first(conn)
-        .optional()
-        .is_ok()
+pub fn query_last_event_seq(conn: &mut DbConnection) -> Result<i64, AppError> {
+    with_conn!(conn, |conn| {
+        EventSchema::did_event
+            .select(diesel::dsl::max(EventSchema::seq))
+            .first::<Option<i64>>(conn)
+            .map(|seq| seq.unwrap_or(0))
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+/// Add an outbox entry for `event` to every webhook.
+#[tracing::instrument(skip_all)]
+pub fn enqueue_deliveries(
+    conn: &mut DbConnection,
+    event: &models::DidEvent,
+) -> Result<(), AppError> {
+    let payload = serde_json::to_value(event).map_err(|e| AppError::RunTimeError(e.to_string()))?;
+    let now = Utc::now();
+    with_conn!(conn, |conn| {
+        let webhook_ids: Vec<i32> = WebhookSchema::webhook
+            .select(WebhookSchema::id)
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        let deliveries: Vec<_> = webhook_ids
+            .into_iter()
+            .map(|webhook_id| models::NewWebhookDelivery {
+                webhook_id,
+                event_seq: event.seq,
+                payload: payload.clone(),
+                status: DeliveryStatus::Pending.as_str().to_string(),
+                attempts: 0,
+                next_attempt_at: now,
+                created_at: now,
+            })
+            .collect();
+        if !deliveries.is_empty() {
+            insert_into(DeliverySchema::webhook_delivery)
+                .values(deliveries)
+                .execute(conn)
+                .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        }
+        Ok(())
+    })
+}
+
+/// Register `url`, or replace its secret when it is registered already.
+#[tracing::instrument(skip_all)]
+pub fn upsert_webhook(
+    conn: &mut DbConnection,
+    url: String,
+    secret: String,
+) -> Result<models::Webhook, AppError> {
+    let webhook = models::NewWebhook {
+        url,
+        secret: secret.clone(),
+        created_at: Utc::now(),
+    };
+    with_conn!(conn, |conn| {
+        insert_into(WebhookSchema::webhook)
+            .values(webhook.clone())
+            .on_conflict(WebhookSchema::url)
+            .do_update()
+            .set(WebhookSchema::secret.eq(secret.clone()))
+            .returning(models::Webhook::as_returning())
+            .get_result(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn delete_webhook(conn: &mut DbConnection, id: i32) -> Result<(), AppError> {
+    with_conn!(conn, |conn| {
+        let deleted = delete(WebhookSchema::webhook.filter(WebhookSchema::id.eq(id)))
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        if deleted == 0 {
+            return Err(AppError::WebhookNotFound(id));
+        }
+        Ok(())
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn query_webhooks(conn: &mut DbConnection) -> Result<Vec<models::Webhook>, AppError> {
+    with_conn!(conn, |conn| {
+        WebhookSchema::webhook
+            .order(WebhookSchema::id.asc())
+            .select(models::Webhook::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+/// Claim up to `limit` pending deliveries whose next attempt is due, oldest
+/// first, by moving their next attempt `lease` ahead. Other workers skip them
+/// until the outcome is recorded, or the lease runs out should the claimer
+/// die. On postgres rows a concurrent claim has locked are skipped rather
+/// than waited for.
+#[tracing::instrument(skip_all)]
+pub fn claim_due_deliveries(
+    conn: &mut DbConnection,
+    limit: i64,
+    lease: TimeDelta,
+) -> Result<Vec<models::WebhookDelivery>, AppError> {
+    let now = Utc::now();
+    conn.transaction(|conn| {
+        let due = || {
+            DeliverySchema::webhook_delivery
+                .filter(DeliverySchema::status.eq(DeliveryStatus::Pending.as_str()))
+                .filter(DeliverySchema::nextAttemptAt.le(UtcTimestamp(now)))
+                .order(DeliverySchema::id.asc())
+                .limit(limit)
+        };
+        let due = match conn {
+            DbConnection::Pg(conn) => due()
+                .for_update()
+                .skip_locked()
+                .select(models::WebhookDelivery::as_select())
+                .load(conn),
+            DbConnection::Sqlite(conn) => due()
+                .select(models::WebhookDelivery::as_select())
+                .load(conn),
+        }
+        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        let ids: Vec<i64> = due.iter().map(|delivery| delivery.id).collect();
+        let lease_end = UtcTimestamp(now + lease);
+        with_conn!(conn, |conn| {
+            update(DeliverySchema::webhook_delivery.filter(DeliverySchema::id.eq_any(ids)))
+                .set(DeliverySchema::nextAttemptAt.eq(lease_end))
+                .execute(conn)
+                .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+        })?;
+        Ok(due)
+    })
+}
+
+/// Deliveries of a webhook, newest first, optionally only those in `status`
+/// and before the delivery `before`.
+#[tracing::instrument(skip_all)]
+pub fn query_deliveries(
+    conn: &mut DbConnection,
+    webhook_id: i32,
+    status: Option<String>,
+    before: Option<i64>,
+    limit: i64,
+) -> Result<Vec<models::WebhookDelivery>, AppError> {
+    with_conn!(conn, |conn| {
+        let mut deliveries = DeliverySchema::webhook_delivery
+            .filter(DeliverySchema::webhookId.eq(webhook_id))
+            .into_boxed();
+        if let Some(status) = &status {
+            deliveries = deliveries.filter(DeliverySchema::status.eq(status.clone()));
+        }
+        if let Some(before) = before {
+            deliveries = deliveries.filter(DeliverySchema::id.lt(before));
+        }
+        deliveries
+            .order(DeliverySchema::id.desc())
+            .limit(limit)
+            .select(models::WebhookDelivery::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+/// Store the outcome of a delivery attempt.
+#[tracing::instrument(skip_all)]
+pub fn update_delivery(
+    conn: &mut DbConnection,
+    delivery: &models::WebhookDelivery,
+) -> Result<(), AppError> {
+    let next_attempt_at = UtcTimestamp(delivery.next_attempt_at);
+    let delivered_at = delivery.delivered_at.map(UtcTimestamp);
+    with_conn!(conn, |conn| {
+        update(DeliverySchema::webhook_delivery.filter(DeliverySchema::id.eq(delivery.id)))
+            .set((
+                DeliverySchema::status.eq(delivery.status.clone()),
+                DeliverySchema::attempts.eq(delivery.attempts),
+                DeliverySchema::nextAttemptAt.eq(next_attempt_at),
+                DeliverySchema::lastError.eq(delivery.last_error.clone()),
+                DeliverySchema::deliveredAt.eq(delivered_at),
+            ))
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        Ok(())
+    })
+}
+
+/// Make a delivery pending and due again.
+#[tracing::instrument(skip_all)]
+pub fn retry_delivery(
+    conn: &mut DbConnection,
+    id: i64,
+) -> Result<models::WebhookDelivery, AppError> {
+    let now = UtcTimestamp(Utc::now());
+    with_conn!(conn, |conn| {
+        update(DeliverySchema::webhook_delivery.filter(DeliverySchema::id.eq(id)))
+            .set((
+                DeliverySchema::status.eq(DeliveryStatus::Pending.as_str()),
+                DeliverySchema::nextAttemptAt.eq(now),
+            ))
+            .returning(models::WebhookDelivery::as_returning())
+            .get_result(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
+            .ok_or(AppError::DeliveryNotFound(id))
+    })
 }
 
 #[tracing::instrument(skip_all)]
 pub fn query_valid_did_doc(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     did: String,
-) -> Result<Web5DocumentData, AppError> {
-    let record = DidRecordSchema::did_record
-        .filter(DidRecordSchema::did.eq(did.clone()))
-        .filter(DidRecordSchema::valid.eq(true))
-        .select(models::DidRecord::as_select())
-        .first(conn)
-        .optional()
-        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
-        .ok_or(AppError::DidDocNotFound(did.clone()))?;
-    Ok(serde_json::from_str(&record.document).map_err(|_| AppError::DidDocNoData(did))?)
+) -> Result<ResolvedDocument, AppError> {
+    with_conn!(conn, |conn| {
+        let record = DidRecordSchema::did_record
+            .filter(DidRecordSchema::did.eq(did.clone()))
+            .filter(DidRecordSchema::valid.eq(true))
+            .select(models::DidRecord::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
+            .ok_or(AppError::DidDocNotFound(did.clone()))?;
+        Ok(ResolvedDocument {
+            document: serde_json::from_value(record.document)
+                .map_err(|_| AppError::DidDocNoData(did))?,
+            tx_hash: record.tx_hash,
+            out_index: record.out_index,
+            document_cid: record.document_cid,
+            height: record.height,
+            block_timestamp: record.block_timestamp,
+        })
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn query_valid_did_doc_cbor(conn: &mut DbConnection, did: String) -> Result<Vec<u8>, AppError> {
+    with_conn!(conn, |conn| {
+        let (document_cbor, cell_data): (Option<Vec<u8>>, Option<Vec<u8>>) =
+            DidRecordSchema::did_record
+                .filter(DidRecordSchema::did.eq(did.clone()))
+                .filter(DidRecordSchema::valid.eq(true))
+                .select((DidRecordSchema::documentCbor, DidRecordSchema::cellData))
+                .first(conn)
+                .optional()
+                .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
+                .ok_or(AppError::DidDocNotFound(did.clone()))?;
+        match (document_cbor, cell_data) {
+            (Some(document_cbor), _) => Ok(document_cbor),
+            (None, Some(cell_data)) => Ok(decode_did_cell(&cell_data)?.document_cbor),
+            (None, None) => Err(AppError::DidDocNoData(did)),
+        }
+    })
 }
 
 #[tracing::instrument(skip_all)]
 pub fn query_valid_did_doc_by_index(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
     tx_hash: String,
     out_index: i32,
 ) -> Result<models::DidRecord, AppError> {
-    DidRecordSchema::did_record
-        .filter(DidRecordSchema::txHash.eq(tx_hash.clone()))
-        .filter(DidRecordSchema::outIndex.eq(out_index))
-        .filter(DidRecordSchema::valid.eq(true))
-        .select(models::DidRecord::as_select())
-        .first(conn)
-        .optional()
-        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
-        .ok_or(AppError::DidDocNotFound(format!(
-            "tx hash: {tx_hash}, tx index: {out_index}"
-        )))
+    with_conn!(conn, |conn| {
+        DidRecordSchema::did_record
+            .filter(DidRecordSchema::txHash.eq(tx_hash.clone()))
+            .filter(DidRecordSchema::outIndex.eq(out_index))
+            .filter(DidRecordSchema::valid.eq(true))
+            .select(models::DidRecord::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
+            .ok_or(AppError::DidDocNotFound(format!(
+                "tx hash: {tx_hash}, tx index: {out_index}"
+            )))
+    })
 }
 
 #[tracing::instrument(skip_all)]
 pub fn query_valid_index_set(
-    conn: &mut PgConnection,
+    conn: &mut DbConnection,
 ) -> Result<Option<Vec<(String, i32)>>, AppError> {
-    DidRecordSchema::did_record
-        .filter(DidRecordSchema::valid.eq(true))
-        .select((DidRecordSchema::txHash, DidRecordSchema::outIndex))
-        .get_results(conn)
-        .optional()
-        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    with_conn!(conn, |conn| {
+        DidRecordSchema::did_record
+            .filter(DidRecordSchema::valid.eq(true))
+            .select((DidRecordSchema::txHash, DidRecordSchema::outIndex))
+            .get_results(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
 }
 
+/// Height of the next block to index: the one after the sync checkpoint.
+/// Databases indexed before the checkpoint was stored resume at their latest
+/// record, whose block is scanned again.
 #[tracing::instrument(skip_all)]
-pub fn query_count(conn: &mut PgConnection) -> Result<i64, AppError> {
-    DidRecordSchema::did_record
-        .order(DidRecordSchema::height.desc())
-        .select(DidRecordSchema::height)
-        .first(conn)
-        .optional()
-        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
-        .ok_or(AppError::CountNotFound)
+pub fn query_resume_height(conn: &mut DbConnection) -> Result<i64, AppError> {
+    if let Some(height) = query_sync_checkpoint(conn)? {
+        return Ok(height + 1);
+    }
+    with_conn!(conn, |conn| {
+        DidRecordSchema::did_record
+            .order(DidRecordSchema::height.desc())
+            .select(DidRecordSchema::height)
+            .first(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
+            .ok_or(AppError::CountNotFound)
+    })
 }
 
 #[tracing::instrument(skip_all)]
-pub fn resolve_valid_handle(conn: &mut PgConnection, handle: String) -> Result<String, AppError> {
-    DidRecordSchema::did_record
-        .filter(DidRecordSchema::handle.eq(handle.clone()))
-        .filter(DidRecordSchema::valid.eq(true))
-        .select(DidRecordSchema::did)
-        .first(conn)
-        .optional()
-        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
-        .ok_or(AppError::HandleNotFound(handle.clone()))
+pub fn resolve_valid_handle(conn: &mut DbConnection, handle: String) -> Result<String, AppError> {
+    with_conn!(conn, |conn| {
+        DidRecordSchema::did_record
+            .filter(DidRecordSchema::handle.eq(handle.clone()))
+            .filter(DidRecordSchema::valid.eq(true))
+            .select(DidRecordSchema::did)
+            .first(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?
+            .ok_or(AppError::HandleNotFound(handle.clone()))
+    })
 }
 
+/// Did of the valid record holding `handle`, which has to be lowercase,
+/// ignoring case.
 #[tracing::instrument(skip_all)]
-pub fn insert_record(
-    conn: &mut PgConnection,
-    did: String,
+pub fn query_handle_owner(
+    conn: &mut DbConnection,
     handle: String,
-    signing_key: String,
-    time_stamp: u64,
-    ckb_addr: String,
-    tx_hash: String,
-    out_index: i32,
-    block_height: i64,
-    doc: Web5DocumentData,
-    valid: bool,
-) -> Result<(), AppError> {
-    let created_at = transfer_time(time_stamp);
-    let doc_str = serde_json::to_string(&doc).map_err(|e| AppError::RunTimeError(e.to_string()))?;
-    let _: String = insert_into(DidRecordSchema::did_record)
-        .values((
-            DidRecordSchema::did.eq(did),
-            DidRecordSchema::handle.eq(handle),
-            DidRecordSchema::signingKey.eq(signing_key),
-            DidRecordSchema::createdAt.eq(created_at),
-            DidRecordSchema::ckbAddress.eq(ckb_addr),
-            DidRecordSchema::document.eq(doc_str),
-            DidRecordSchema::txHash.eq(tx_hash),
-            DidRecordSchema::outIndex.eq(out_index),
-            DidRecordSchema::height.eq(block_height),
-            DidRecordSchema::valid.eq(valid),
-        ))
-        .on_conflict_do_nothing()
-        .returning(DidRecordSchema::did)
-        .get_result(conn)
-        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
-    Ok(())
+) -> Result<Option<String>, AppError> {
+    with_conn!(conn, |conn| {
+        DidRecordSchema::did_record
+            .filter(lower(DidRecordSchema::handle).eq(handle))
+            .filter(DidRecordSchema::valid.eq(true))
+            .select(DidRecordSchema::did)
+            .first(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+/// Valid records whose lowercased handle starts with `prefix`, which has to be
+/// lowercase already, in handle order.
+#[tracing::instrument(skip_all)]
+pub fn search_handles(
+    conn: &mut DbConnection,
+    prefix: String,
+    limit: i64,
+) -> Result<Vec<HandleMatch>, AppError> {
+    let handles: Vec<(Option<String>, String)> = match conn {
+        // Served by record_handle_prefix_idx (text_pattern_ops).
+        DbConnection::Pg(conn) => DidRecordSchema::did_record
+            .filter(DidRecordSchema::valid.eq(true))
+            .filter(
+                lower(DidRecordSchema::handle)
+                    .like(like_prefix(&prefix))
+                    .escape('\\'),
+            )
+            .order(lower(DidRecordSchema::handle).asc())
+            .limit(limit)
+            .select((DidRecordSchema::handle, DidRecordSchema::did))
+            .load(conn),
+        // A range scan on record_handle_lower_idx, SQLite only uses indexes for
+        // LIKE on plain columns.
+        DbConnection::Sqlite(conn) => DidRecordSchema::did_record
+            .filter(DidRecordSchema::valid.eq(true))
+            .filter(lower(DidRecordSchema::handle).ge(prefix.clone()))
+            .filter(lower(DidRecordSchema::handle).lt(format!("{prefix}\u{10FFFF}")))
+            .order(lower(DidRecordSchema::handle).asc())
+            .limit(limit)
+            .select((DidRecordSchema::handle, DidRecordSchema::did))
+            .load(conn),
+    }
+    .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+    Ok(handles
+        .into_iter()
+        .filter_map(|(handle, did)| {
+            Some(HandleMatch {
+                handle: handle?,
+                did,
+            })
+        })
+        .collect())
+}
+
+/// Did, handle and document of the valid records matching any of `dids` or
+/// `handles`, in a single query. `handles` have to be lowercase and match
+/// ignoring case.
+#[tracing::instrument(skip_all)]
+pub fn query_valid_records_in(
+    conn: &mut DbConnection,
+    dids: Vec<String>,
+    handles: Vec<String>,
+) -> Result<Vec<(String, Option<String>, serde_json::Value)>, AppError> {
+    with_conn!(conn, |conn| {
+        DidRecordSchema::did_record
+            .filter(
+                DidRecordSchema::did
+                    .eq_any(dids.clone())
+                    .or(lower(DidRecordSchema::handle).eq_any(handles.clone())),
+            )
+            .filter(DidRecordSchema::valid.eq(true))
+            .select((
+                DidRecordSchema::did,
+                DidRecordSchema::handle,
+                DidRecordSchema::document,
+            ))
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn resolve_valid_local_id(
+    conn: &mut DbConnection,
+    local_id: String,
+) -> Result<Vec<String>, AppError> {
+    with_conn!(conn, |conn| {
+        let dids: Vec<String> = DidRecordSchema::did_record
+            .filter(DidRecordSchema::localId.eq(local_id.clone()))
+            .filter(DidRecordSchema::valid.eq(true))
+            .order(DidRecordSchema::height.asc())
+            .select(DidRecordSchema::did)
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        if dids.is_empty() {
+            return Err(AppError::LocalIdNotFound(local_id));
+        }
+        Ok(dids)
+    })
+}
+
+/// Store `record`. False when the did already has the record of the same
+/// cell, e.g. a block scanned again, conflicting with any other record is an
+/// error.
+#[tracing::instrument(skip_all)]
+pub fn insert_record(
+    conn: &mut DbConnection,
+    record: &models::NewDidRecord,
+) -> Result<bool, AppError> {
+    with_conn!(conn, |conn| {
+        let inserted = insert_into(DidRecordSchema::did_record)
+            .values(record.clone())
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        if inserted > 0 {
+            return Ok(true);
+        }
+        let stored: i64 = DidRecordSchema::did_record
+            .filter(DidRecordSchema::did.eq(record.did.clone()))
+            .filter(DidRecordSchema::txHash.eq(record.tx_hash.clone()))
+            .filter(DidRecordSchema::outIndex.eq(record.out_index))
+            .count()
+            .get_result(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        if stored == 0 {
+            return Err(AppError::RecordConflict(record.did.clone()));
+        }
+        Ok(false)
+    })
 }
 
 #[tracing::instrument(skip_all)]
 pub fn update_record(
-    conn: &mut PgConnection,
-    did: String,
-    handle: String,
-    time_stamp: u64,
-    tx_hash: String,
-    out_index: i32,
-    block_height: i64,
-    doc: Web5DocumentData,
+    conn: &mut DbConnection,
+    record: &models::NewDidRecord,
 ) -> Result<(), AppError> {
-    let created_at = transfer_time(time_stamp);
-    let doc_str = serde_json::to_string(&doc).map_err(|e| AppError::RunTimeError(e.to_string()))?;
-    update(DidRecordSchema::did_record)
-        .filter(DidRecordSchema::did.eq(did))
-        .set((
-            DidRecordSchema::handle.eq(handle),
-            DidRecordSchema::createdAt.eq(created_at),
-            DidRecordSchema::document.eq(doc_str),
-            DidRecordSchema::txHash.eq(tx_hash),
-            DidRecordSchema::outIndex.eq(out_index),
-            DidRecordSchema::height.eq(block_height),
-        ))
-        .execute(conn)
-        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
-    Ok(())
+    with_conn!(conn, |conn| {
+        update(DidRecordSchema::did_record)
+            .filter(DidRecordSchema::did.eq(record.did.clone()))
+            .set(record.clone())
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        Ok(())
+    })
 }
 
 #[tracing::instrument(skip_all)]
 pub fn delete_record(
-    conn: &mut PgConnection,
-    did: String,
-    handle: String,
-    signing_key: String,
+    conn: &mut DbConnection,
+    record: models::DidRecord,
     time_stamp: u64,
-    ckb_addr: String,
     tx_hash: String,
     in_index: i32,
     block_height: i64,
-    doc: String,
 ) -> Result<(), AppError> {
-    delete(DidRecordSchema::did_record)
-        .filter(DidRecordSchema::did.eq(did.clone()))
-        .execute(conn)
-        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+    with_conn!(conn, |conn| {
+        delete(DidRecordSchema::did_record)
+            .filter(DidRecordSchema::did.eq(record.did.clone()))
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
 
-    let deleted_at = transfer_time(time_stamp);
-    let _: String = insert_into(DidDeleteSchema::did_delete_record)
-        .values((
-            DidDeleteSchema::did.eq(did),
-            DidDeleteSchema::handle.eq(handle),
-            DidDeleteSchema::signingKey.eq(signing_key),
-            DidDeleteSchema::deletedAt.eq(deleted_at),
-            DidDeleteSchema::ckbAddress.eq(ckb_addr),
-            DidDeleteSchema::document.eq(doc),
-            DidDeleteSchema::txHash.eq(tx_hash),
-            DidDeleteSchema::inIndex.eq(in_index),
-            DidDeleteSchema::height.eq(block_height),
-        ))
-        .on_conflict_do_nothing()
-        .returning(DidDeleteSchema::did)
-        .get_result(conn)
-        .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
-    Ok(())
+        let delete_record = models::DidDeleteRecord {
+            did: record.did,
+            ckb_address: record.ckb_address,
+            handle: record.handle,
+            signing_key: record.signing_key,
+            tx_hash,
+            in_index,
+            document: record.document,
+            height: block_height,
+            deleted_at: block_time(time_stamp),
+            block_timestamp: time_stamp as i64,
+            deployment: record.deployment,
+        };
+        let inserted = insert_into(DidDeleteSchema::did_delete_record)
+            .values(delete_record.clone())
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        if inserted == 0 {
+            return Err(AppError::RecordConflict(delete_record.did));
+        }
+        Ok(())
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn delete_record_by_index(
+    conn: &mut DbConnection,
+    tx_hash: String,
+    out_index: i32,
+) -> Result<usize, AppError> {
+    with_conn!(conn, |conn| {
+        delete(DidRecordSchema::did_record)
+            .filter(DidRecordSchema::txHash.eq(tx_hash))
+            .filter(DidRecordSchema::outIndex.eq(out_index))
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn insert_did_cell(conn: &mut DbConnection, cell: &models::DidCell) -> Result<(), AppError> {
+    with_conn!(conn, |conn| {
+        insert_into(DidCellSchema::did_cell)
+            .values(cell)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        Ok(())
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn spend_did_cell(
+    conn: &mut DbConnection,
+    tx_hash: String,
+    out_index: i32,
+    spent_tx_hash: String,
+    spent_in_index: i32,
+    spent_height: i64,
+) -> Result<(), AppError> {
+    with_conn!(conn, |conn| {
+        update(DidCellSchema::did_cell)
+            .filter(DidCellSchema::txHash.eq(tx_hash))
+            .filter(DidCellSchema::outIndex.eq(out_index))
+            .set((
+                DidCellSchema::spentTxHash.eq(spent_tx_hash),
+                DidCellSchema::spentInIndex.eq(spent_in_index),
+                DidCellSchema::spentHeight.eq(spent_height),
+            ))
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        Ok(())
+    })
+}
+
+/// Add the `did_cell` rows missing for records indexed before cells were
+/// stored, from the cell data kept with the record. Returns how many were
+/// added and how many records lack the cell data to add one from.
+#[tracing::instrument(skip_all)]
+pub fn backfill_did_cells(conn: &mut DbConnection) -> Result<(usize, usize), AppError> {
+    with_conn!(conn, |conn| {
+        let records: Vec<models::DidRecord> = DidRecordSchema::did_record
+            .filter(diesel::dsl::not(diesel::dsl::exists(
+                DidCellSchema::did_cell
+                    .filter(DidCellSchema::txHash.eq(DidRecordSchema::txHash))
+                    .filter(DidCellSchema::outIndex.eq(DidRecordSchema::outIndex)),
+            )))
+            .select(models::DidRecord::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        let (mut added, mut missing) = (0, 0);
+        for record in records {
+            let Some(cell_data) = record.cell_data else {
+                missing += 1;
+                continue;
+            };
+            insert_into(DidCellSchema::did_cell)
+                .values(models::DidCell {
+                    tx_hash: record.tx_hash,
+                    out_index: record.out_index,
+                    did: record.did,
+                    ckb_address: record.ckb_address,
+                    deployment: record.deployment,
+                    cell_data,
+                    height: record.height,
+                    block_timestamp: record.block_timestamp,
+                    ..Default::default()
+                })
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+            added += 1;
+        }
+        Ok((added, missing))
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn query_live_cell_set(conn: &mut DbConnection) -> Result<Vec<(String, i32)>, AppError> {
+    with_conn!(conn, |conn| {
+        DidCellSchema::did_cell
+            .filter(DidCellSchema::spentTxHash.is_null())
+            .select((DidCellSchema::txHash, DidCellSchema::outIndex))
+            .get_results(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn query_live_cells(conn: &mut DbConnection) -> Result<Vec<models::DidCell>, AppError> {
+    with_conn!(conn, |conn| {
+        DidCellSchema::did_cell
+            .filter(DidCellSchema::spentTxHash.is_null())
+            .order((DidCellSchema::height.asc(), DidCellSchema::outIndex.asc()))
+            .select(models::DidCell::as_select())
+            .get_results(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn insert_invalid_cell(
+    conn: &mut DbConnection,
+    invalid_cell: &models::InvalidCell,
+) -> Result<(), AppError> {
+    with_conn!(conn, |conn| {
+        insert_into(InvalidCellSchema::invalid_cell)
+            .values(invalid_cell.clone())
+            .on_conflict((InvalidCellSchema::txHash, InvalidCellSchema::outIndex))
+            .do_update()
+            .set((
+                InvalidCellSchema::category.eq(invalid_cell.category.clone()),
+                InvalidCellSchema::reason.eq(invalid_cell.reason.clone()),
+            ))
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        Ok(())
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn delete_invalid_cell(
+    conn: &mut DbConnection,
+    tx_hash: String,
+    out_index: i32,
+) -> Result<usize, AppError> {
+    with_conn!(conn, |conn| {
+        delete(InvalidCellSchema::invalid_cell)
+            .filter(InvalidCellSchema::txHash.eq(tx_hash))
+            .filter(InvalidCellSchema::outIndex.eq(out_index))
+            .execute(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+#[tracing::instrument(skip_all)]
+pub fn query_invalid_cells(
+    conn: &mut DbConnection,
+    since: i64,
+    limit: i64,
+) -> Result<Vec<models::InvalidCell>, AppError> {
+    with_conn!(conn, |conn| {
+        InvalidCellSchema::invalid_cell
+            .filter(InvalidCellSchema::height.ge(since))
+            .order((
+                InvalidCellSchema::height.asc(),
+                InvalidCellSchema::txHash.asc(),
+            ))
+            .limit(limit)
+            .select(models::InvalidCell::as_select())
+            .get_results(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+/// Valid records matching the filters of `query`, in `(height, txHash,
+/// outIndex)` order and starting after `after`.
+#[tracing::instrument(skip_all)]
+pub fn query_did_page(
+    conn: &mut DbConnection,
+    query: DidListQuery,
+    after: Option<DidCursor>,
+    limit: i64,
+) -> Result<Vec<models::DidSummary>, AppError> {
+    let pg = matches!(conn, DbConnection::Pg(_));
+    with_conn!(conn, |conn| {
+        let mut records = DidRecordSchema::did_record
+            .filter(DidRecordSchema::valid.eq(true))
+            .into_boxed();
+        if let Some(prefix) = &query.handle_prefix {
+            records = records.filter(
+                lower(DidRecordSchema::handle)
+                    .like(like_prefix(prefix))
+                    .escape('\\'),
+            );
+        }
+        if let Some(from_height) = query.from_height {
+            records = records.filter(DidRecordSchema::height.ge(from_height));
+        }
+        if let Some(to_height) = query.to_height {
+            records = records.filter(DidRecordSchema::height.le(to_height));
+        }
+        if let Some(created_from) = query.created_from {
+            records = records.filter(DidRecordSchema::createdAt.ge(UtcTimestamp(created_from)));
+        }
+        if let Some(created_to) = query.created_to {
+            records = records.filter(DidRecordSchema::createdAt.le(UtcTimestamp(created_to)));
+        }
+        if let Some(address) = &query.address {
+            records = records.filter(DidRecordSchema::ckbAddress.eq(address.clone()));
+        }
+        // The document filters use the GIN index through `@?` on postgres and
+        // the json functions on SQLite.
+        if let Some(method_id) = &query.verification_method {
+            let path = format!("$.verificationMethods.{}", jsonpath_str(method_id));
+            records = records.filter(if pg {
+                sql::<Bool>("\"document\" @? CAST(")
+                    .bind::<Text, _>(path)
+                    .sql(" AS jsonpath)")
+            } else {
+                sql::<Bool>("json_type(\"document\", ")
+                    .bind::<Text, _>(path)
+                    .sql(") IS NOT NULL")
+            });
+        }
+        if let Some(service_type) = &query.service_type {
+            records = records.filter(if pg {
+                sql::<Bool>("\"document\" @? CAST(")
+                    .bind::<Text, _>(format!(
+                        "$.services.* ? (@.type == {})",
+                        jsonpath_str(service_type)
+                    ))
+                    .sql(" AS jsonpath)")
+            } else {
+                sql::<Bool>(
+                    "EXISTS (SELECT 1 FROM json_each(\"document\", '$.services') \
+                     WHERE json_extract(value, '$.type') = ",
+                )
+                .bind::<Text, _>(service_type.clone())
+                .sql(")")
+            });
+        }
+        if let Some(also_known_as) = &query.also_known_as {
+            records = records.filter(if pg {
+                sql::<Bool>("\"document\" @? CAST(")
+                    .bind::<Text, _>(format!(
+                        "$.alsoKnownAs[*] ? (@ == {})",
+                        jsonpath_str(also_known_as)
+                    ))
+                    .sql(" AS jsonpath)")
+            } else {
+                sql::<Bool>(
+                    "EXISTS (SELECT 1 FROM json_each(\"document\", '$.alsoKnownAs') \
+                     WHERE value = ",
+                )
+                .bind::<Text, _>(also_known_as.clone())
+                .sql(")")
+            });
+        }
+        if let Some(after) = &after {
+            records = records.filter(
+                DidRecordSchema::height
+                    .gt(after.height)
+                    .or(DidRecordSchema::height.eq(after.height).and(
+                        DidRecordSchema::txHash.gt(after.tx_hash.clone()).or(
+                            DidRecordSchema::txHash
+                                .eq(after.tx_hash.clone())
+                                .and(DidRecordSchema::outIndex.gt(after.out_index)),
+                        ),
+                    )),
+            );
+        }
+        records
+            .order((
+                DidRecordSchema::height.asc(),
+                DidRecordSchema::txHash.asc(),
+                DidRecordSchema::outIndex.asc(),
+            ))
+            .limit(limit)
+            .select(models::DidSummary::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))
+    })
+}
+
+/// Live and removed records matching `filter`, latest change first.
+#[tracing::instrument(skip_all)]
+pub fn query_history(
+    conn: &mut DbConnection,
+    filter: HistoryFilter,
+    limit: i64,
+) -> Result<Vec<HistoryEntry>, AppError> {
+    type Removal = (
+        String,
+        Option<String>,
+        String,
+        String,
+        i32,
+        i64,
+        DateTime<Utc>,
+    );
+    type CellOrigin = (String, i32, i64, i64, Option<String>, Option<i32>);
+    let (records, removals, origins) = with_conn!(conn, |conn| {
+        let mut records = DidRecordSchema::did_record
+            .filter(DidRecordSchema::valid.eq(true))
+            .into_boxed();
+        let mut removals = DidDeleteSchema::did_delete_record.into_boxed();
+        match &filter {
+            HistoryFilter::Handle(handle) => {
+                records = records.filter(lower(DidRecordSchema::handle).eq(handle.clone()));
+                removals = removals.filter(lower(DidDeleteSchema::handle).eq(handle.clone()));
+            }
+            HistoryFilter::Address(address) => {
+                records = records.filter(DidRecordSchema::ckbAddress.eq(address.clone()));
+                removals = removals.filter(DidDeleteSchema::ckbAddress.eq(address.clone()));
+            }
+        }
+        let records: Vec<models::DidSummary> = records
+            .order(DidRecordSchema::height.desc())
+            .limit(limit)
+            .select(models::DidSummary::as_select())
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        let removals: Vec<Removal> = removals
+            .order(DidDeleteSchema::height.desc())
+            .limit(limit)
+            .select((
+                DidDeleteSchema::did,
+                DidDeleteSchema::handle,
+                DidDeleteSchema::ckbAddress,
+                DidDeleteSchema::txHash,
+                DidDeleteSchema::inIndex,
+                DidDeleteSchema::height,
+                DidDeleteSchema::deletedAt,
+            ))
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        // The cells the removed records were read from.
+        let spent_by: Vec<String> = removals.iter().map(|r| r.3.clone()).collect();
+        let origins: Vec<CellOrigin> = DidCellSchema::did_cell
+            .filter(DidCellSchema::spentTxHash.eq_any(spent_by))
+            .select((
+                DidCellSchema::txHash,
+                DidCellSchema::outIndex,
+                DidCellSchema::height,
+                DidCellSchema::blockTimestamp,
+                DidCellSchema::spentTxHash,
+                DidCellSchema::spentInIndex,
+            ))
+            .load(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        Ok::<_, AppError>((records, removals, origins))
+    })?;
+
+    let mut history: Vec<HistoryEntry> = records
+        .into_iter()
+        .map(|record| HistoryEntry {
+            did: record.did,
+            handle: record.handle,
+            ckb_address: record.ckb_address,
+            created: Some(HistoryPoint {
+                tx_hash: record.tx_hash,
+                index: record.out_index,
+                height: record.height,
+                time: record.created_at,
+            }),
+            removed: None,
+        })
+        .collect();
+    for (did, handle, ckb_address, tx_hash, in_index, height, deleted_at) in removals {
+        let created = origins
+            .iter()
+            .find(|(.., spent_tx, spent_index)| {
+                spent_tx.as_ref() == Some(&tx_hash) && *spent_index == Some(in_index)
+            })
+            .map(|(tx_hash, out_index, height, timestamp, ..)| HistoryPoint {
+                tx_hash: tx_hash.clone(),
+                index: *out_index,
+                height: *height,
+                time: block_time(*timestamp as u64),
+            });
+        history.push(HistoryEntry {
+            did,
+            handle,
+            ckb_address,
+            created,
+            removed: Some(HistoryPoint {
+                tx_hash,
+                index: in_index,
+                height,
+                time: deleted_at,
+            }),
+        });
+    }
+    history.sort_by_key(|entry| std::cmp::Reverse(entry.height()));
+    history.truncate(limit as usize);
+    Ok(history)
+}
+
+#[tracing::instrument(skip_all)]
+pub fn query_did_status(conn: &mut DbConnection, did: String) -> Result<DidStatus, AppError> {
+    with_conn!(conn, |conn| {
+        let record = DidRecordSchema::did_record
+            .filter(DidRecordSchema::did.eq(did.clone()))
+            .select(models::DidRecord::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        let delete_record = DidDeleteSchema::did_delete_record
+            .filter(DidDeleteSchema::did.eq(did.clone()))
+            .order(DidDeleteSchema::height.desc())
+            .select(models::DidDeleteRecord::as_select())
+            .first(conn)
+            .optional()
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+        let rejections = InvalidCellSchema::invalid_cell
+            .filter(InvalidCellSchema::did.eq(did.clone()))
+            .order(InvalidCellSchema::height.desc())
+            .limit(20)
+            .select(models::InvalidCell::as_select())
+            .get_results(conn)
+            .map_err(|e| AppError::DbExecuteFailed(e.to_string()))?;
+
+        let last_rejected = rejections.first().map(|cell| cell.height);
+        let status = match (&record, &delete_record) {
+            (Some(record), _) if record.valid => "valid",
+            (_, Some(deleted)) if last_rejected.is_none_or(|height| height <= deleted.height) => {
+                "deactivated"
+            }
+            _ if last_rejected.is_some() => "invalid",
+            _ => return Err(AppError::DidDocNotFound(did)),
+        };
+        Ok(DidStatus {
+            did,
+            status: status.to_string(),
+            record,
+            delete_record,
+            rejections,
+        })
+    })
+}
+
+/// `LIKE` pattern matching the values that start with `prefix`, escaped with `\\`.
+fn like_prefix(prefix: &str) -> String {
+    format!(
+        "{}%",
+        prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    )
+}
+
+/// Quoted json path label, as used in `@?` queries on postgres and in
+/// `json_*` paths on SQLite.
+fn jsonpath_str(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn sqlite_memory() -> DbConnection {
+        let mut conn = DbConnection::establish("sqlite://:memory:").unwrap();
+        run_migrations(&mut conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn embedded_migrations_apply_on_request() {
+        let migrations =
+            std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations/sqlite"))
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().path().is_dir())
+                .count();
+        assert!(migrations > 0);
+        let mut conn = DbConnection::establish("sqlite://:memory:").unwrap();
+
+        // `--no-migrate` leaves a fresh database alone.
+        prepare_schema(&mut conn, false).unwrap();
+        assert_eq!(pending_migration_count(&mut conn).unwrap(), migrations);
+        assert_eq!(schema_version(&mut conn).unwrap(), None);
+        assert!(!check_connection(&mut conn));
+
+        prepare_schema(&mut conn, true).unwrap();
+        assert_eq!(pending_migration_count(&mut conn).unwrap(), 0);
+        let version = schema_version(&mut conn).unwrap().unwrap();
+        assert!(check_connection(&mut conn));
+
+        // Nothing left to apply on the next start.
+        prepare_schema(&mut conn, true).unwrap();
+        assert_eq!(schema_version(&mut conn).unwrap(), Some(version));
+    }
+
+    fn record(did: &str, handle: Option<&str>, height: i64) -> models::NewDidRecord {
+        models::NewDidRecord {
+            did: did.to_string(),
+            ckb_address: format!("ckt1{did}"),
+            handle: handle.map(str::to_string),
+            tx_hash: format!("{height:064x}"),
+            document: serde_json::json!({}),
+            height,
+            valid: true,
+            deployment: "default".to_string(),
+            service_errors: serde_json::json!({}),
+            ..Default::default()
+        }
+    }
+
+    fn page(conn: &mut DbConnection, query: DidListQuery) -> Vec<String> {
+        query_did_page(conn, query, None, 10)
+            .unwrap()
+            .into_iter()
+            .map(|summary| summary.did)
+            .collect()
+    }
+
+    #[test]
+    fn did_page_filters_on_sqlite() {
+        let mut conn = sqlite_memory();
+        insert_record(
+            &mut conn,
+            &record("did:web5:a", Some("Alice.example.com"), 1),
+        )
+        .unwrap();
+        insert_record(
+            &mut conn,
+            &record("did:web5:b", Some("al_x.example.com"), 2),
+        )
+        .unwrap();
+        insert_record(&mut conn, &record("did:web5:c", None, 3)).unwrap();
+
+        assert_eq!(
+            page(&mut conn, DidListQuery::default()),
+            ["did:web5:a", "did:web5:b", "did:web5:c"]
+        );
+        // Matched on the lowercased handle, the router normalizes the prefix.
+        let query = DidListQuery {
+            handle_prefix: Some("ali".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(page(&mut conn, query), ["did:web5:a"]);
+        // `_` is a literal, not a wildcard.
+        let query = DidListQuery {
+            handle_prefix: Some("al_".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(page(&mut conn, query), ["did:web5:b"]);
+        let query = DidListQuery {
+            from_height: Some(2),
+            to_height: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(page(&mut conn, query), ["did:web5:b"]);
+
+        let after = DidCursor {
+            height: 1,
+            tx_hash: format!("{:064x}", 1),
+            out_index: 0,
+        };
+        let rest = query_did_page(&mut conn, DidListQuery::default(), Some(after), 1).unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].did, "did:web5:b");
+    }
+
+    #[test]
+    fn history_is_ordered_by_latest_change() {
+        let mut conn = sqlite_memory();
+        let handle = "alice.example.com";
+        // Only one record holds a handle at a time.
+        let mut hold = |did: &str, height: i64, removed_at: i64| {
+            insert_record(&mut conn, &record(did, Some("Alice.example.com"), height)).unwrap();
+            let removed =
+                query_valid_did_doc_by_index(&mut conn, format!("{height:064x}"), 0).unwrap();
+            let spent_by = format!("{removed_at:064x}");
+            delete_record(&mut conn, removed, 0, spent_by, 0, removed_at).unwrap();
+        };
+        hold("did:web5:e", 2, 3);
+        hold("did:web5:a", 1, 5);
+        insert_record(&mut conn, &record("did:web5:b", Some(handle), 6)).unwrap();
+        insert_record(&mut conn, &record("did:web5:d", Some("bob.example.com"), 9)).unwrap();
+
+        let filter = || HistoryFilter::Handle(handle.to_string());
+        let history = query_history(&mut conn, filter(), 10).unwrap();
+        let order: Vec<(&str, i64)> = history
+            .iter()
+            .map(|entry| (entry.did.as_str(), entry.height()))
+            .collect();
+        assert_eq!(
+            order,
+            [("did:web5:b", 6), ("did:web5:a", 5), ("did:web5:e", 3)]
+        );
+        assert!(history[0].removed.is_none());
+        // Removed before did cells were stored, so where it started is unknown.
+        assert!(history[1].created.is_none());
+
+        let latest = query_history(&mut conn, filter(), 2).unwrap();
+        assert_eq!(latest.len(), 2);
+        assert_eq!(latest[1].did, "did:web5:a");
+    }
+
+    #[test]
+    fn notify_payload_is_compact() {
+        let event = models::DidEvent {
+            seq: 42,
+            did: "did:web5:a".to_string(),
+            kind: "handle_change".to_string(),
+            handle: Some("alice.example.com".to_string()),
+            height: 18_000_000,
+            previous_handle: Some("old.example.com".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            notify_payload(&event),
+            serde_json::json!({
+                "seq": 42,
+                "did": "did:web5:a",
+                "handle": "alice.example.com",
+                "op": "handle_change",
+                "height": 18_000_000,
+            })
+        );
+    }
+
+    #[test]
+    fn records_in_match_handles_ignoring_case() {
+        let mut conn = sqlite_memory();
+        insert_record(
+            &mut conn,
+            &record("did:web5:a", Some("Alice.example.com"), 1),
+        )
+        .unwrap();
+        insert_record(&mut conn, &record("did:web5:b", Some("bob.example.com"), 2)).unwrap();
+
+        let records = query_valid_records_in(
+            &mut conn,
+            vec!["did:web5:b".to_string()],
+            vec!["alice.example.com".to_string()],
+        )
+        .unwrap();
+        let mut dids: Vec<&str> = records.iter().map(|(did, _, _)| did.as_str()).collect();
+        dids.sort();
+        assert_eq!(dids, ["did:web5:a", "did:web5:b"]);
+    }
 }
@@ -0,0 +1,248 @@
+use crate::{
+    cell_data::{DidWeb5DataV1, StringOpt},
+    error::AppError,
+    types::Web5DocumentData,
+};
+use data_encoding::BASE64_NOPAD;
+use ipld_core::{
+    cid::{Cid, multihash::Multihash},
+    ipld::Ipld,
+};
+use molecule::{NUMBER_SIZE, prelude::Entity, unpack_number};
+use sha2::{Digest, Sha256};
+
+const DAG_CBOR_CODEC: u64 = 0x71;
+const SHA2_256_CODE: u64 = 0x12;
+
+/// A did:web5 cell decoded by one of the versioned decoders.
+#[derive(Debug, Clone)]
+pub struct DecodedDidCell {
+    pub version: u32,
+    pub document: Web5DocumentData,
+    pub document_cbor: Vec<u8>,
+    pub local_id: Option<String>,
+}
+
+/// Decoder of one `DidWeb5DataUnion` variant.
+///
+/// A new union variant only needs a new decoder appended to `DECODERS`.
+pub trait DidDataDecoder: Sync {
+    /// Molecule union item id of the variant.
+    fn item_id(&self) -> u32;
+
+    /// Decode the variant body, i.e. the cell data without the item id header.
+    fn decode(&self, body: &[u8]) -> Result<DecodedDidCell, AppError>;
+}
+
+pub struct DidWeb5DataV1Decoder;
+
+impl DidDataDecoder for DidWeb5DataV1Decoder {
+    fn item_id(&self) -> u32 {
+        0
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<DecodedDidCell, AppError> {
+        // Fields appended to the V1 table in a compatible upgrade are ignored.
+        let did_data_v1 = DidWeb5DataV1::from_compatible_slice(body)
+            .map_err(|e| AppError::MoleculeError(format!("DidWeb5DataV1 decode failed: {e}")))?;
+        let document_cbor = did_data_v1.document().raw_data().to_vec();
+        Ok(DecodedDidCell {
+            version: self.item_id(),
+            document: parse_document(&document_cbor)?,
+            document_cbor,
+            local_id: parse_local_id(&did_data_v1.local_id())?,
+        })
+    }
+}
+
+static DECODERS: &[&dyn DidDataDecoder] = &[&DidWeb5DataV1Decoder];
+
+pub fn decode_did_cell(cell_data: &[u8]) -> Result<DecodedDidCell, AppError> {
+    if cell_data.len() < NUMBER_SIZE {
+        return Err(AppError::MoleculeError(format!(
+            "DidWeb5Data too short: {} bytes",
+            cell_data.len()
+        )));
+    }
+    let item_id = unpack_number(cell_data);
+    let decoder = DECODERS
+        .iter()
+        .find(|decoder| decoder.item_id() == item_id)
+        .ok_or(AppError::UnknownDidDataVersion(item_id))?;
+    decoder.decode(&cell_data[NUMBER_SIZE..])
+}
+
+pub fn parse_document(bytes: &[u8]) -> Result<Web5DocumentData, AppError> {
+    let ipld: Ipld = serde_ipld_dagcbor::from_slice(bytes).map_err(|e| {
+        AppError::DagCborError(format!(
+            "Web5DocumentData dag cbor decode failed: {e:?}, please update cell."
+        ))
+    })?;
+    serde_json::from_value(ipld_to_json(ipld)).map_err(|e| {
+        AppError::DagCborError(format!(
+            "Web5DocumentData format error: {e}, please update cell."
+        ))
+    })
+}
+
+/// CIDv1 (dag-cbor, sha2-256) of the document bytes, in the default base32 form.
+pub fn document_cid(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    let hash = Multihash::<64>::wrap(SHA2_256_CODE, &digest).expect("sha2-256 digest fits");
+    Cid::new_v1(DAG_CBOR_CODEC, hash).to_string()
+}
+
+/// Render ipld as json in the atproto data model convention: bytes become
+/// `{"$bytes": <base64>}` and links `{"$link": <cid>}`. DAG-JSON would write
+/// `{"/": {"bytes": <base64>}}` and `{"/": <cid>}` instead.
+fn ipld_to_json(ipld: Ipld) -> serde_json::Value {
+    match ipld {
+        Ipld::Null => serde_json::Value::Null,
+        Ipld::Bool(b) => serde_json::Value::Bool(b),
+        Ipld::Integer(i) => match i64::try_from(i) {
+            Ok(i) => i.into(),
+            Err(_) => match u64::try_from(i) {
+                Ok(u) => u.into(),
+                Err(_) => i.to_string().into(),
+            },
+        },
+        Ipld::Float(f) => f.into(),
+        Ipld::String(s) => s.into(),
+        Ipld::Bytes(bytes) => serde_json::json!({ "$bytes": BASE64_NOPAD.encode(&bytes) }),
+        Ipld::List(list) => list.into_iter().map(ipld_to_json).collect(),
+        Ipld::Map(map) => map
+            .into_iter()
+            .map(|(k, v)| (k, ipld_to_json(v)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        Ipld::Link(cid) => serde_json::json!({ "$link": cid.to_string() }),
+    }
+}
+
+pub fn parse_local_id(local_id: &StringOpt) -> Result<Option<String>, AppError> {
+    match local_id.to_opt() {
+        Some(local_id) => String::from_utf8(local_id.raw_data().to_vec())
+            .map(Some)
+            .map_err(|e| AppError::MoleculeError(format!("local_id is not valid utf-8: {e}"))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::cell_data::{Bytes, DidWeb5Data, DidWeb5DataV1, String as MolString};
+    use molecule::prelude::{Builder, Byte};
+
+    fn bytes(data: &[u8]) -> Vec<Byte> {
+        data.iter().copied().map(Byte::new).collect()
+    }
+
+    /// Cell data of a V1 did cell holding `document`.
+    pub(crate) fn encode_did_cell(document: &serde_json::Value, local_id: Option<&str>) -> Vec<u8> {
+        let cbor = serde_ipld_dagcbor::to_vec(document).unwrap();
+        let local_id =
+            local_id.map(|id| MolString::new_builder().set(bytes(id.as_bytes())).build());
+        let v1 = DidWeb5DataV1::new_builder()
+            .document(Bytes::new_builder().set(bytes(&cbor)).build())
+            .local_id(StringOpt::new_builder().set(local_id).build())
+            .build();
+        DidWeb5Data::new_builder()
+            .set(v1)
+            .build()
+            .as_slice()
+            .to_vec()
+    }
+
+    fn document() -> serde_json::Value {
+        serde_json::json!({
+            "verificationMethods": {"atproto": "did:key:zQ3sh"},
+            "alsoKnownAs": ["at://alice.example.com"],
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.example.com"
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn v1_cell_is_decoded() {
+        let decoded = decode_did_cell(&encode_did_cell(&document(), Some("did:plc:abc"))).unwrap();
+        assert_eq!(decoded.version, 0);
+        assert_eq!(decoded.local_id.as_deref(), Some("did:plc:abc"));
+        assert_eq!(decoded.document.also_known_as, ["at://alice.example.com"]);
+        assert_eq!(
+            decoded.document_cbor,
+            serde_ipld_dagcbor::to_vec(&document()).unwrap()
+        );
+        let decoded = decode_did_cell(&encode_did_cell(&document(), None)).unwrap();
+        assert_eq!(decoded.local_id, None);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut data = encode_did_cell(&document(), None);
+        data[..NUMBER_SIZE].copy_from_slice(&7u32.to_le_bytes());
+        assert!(matches!(
+            decode_did_cell(&data),
+            Err(AppError::UnknownDidDataVersion(7))
+        ));
+    }
+
+    #[test]
+    fn data_without_a_version_is_rejected() {
+        for data in [&[][..], &[0, 0, 0][..]] {
+            assert!(matches!(
+                decode_did_cell(data),
+                Err(AppError::MoleculeError(message)) if message.contains("too short")
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_body_is_rejected() {
+        let data = encode_did_cell(&document(), None);
+        for data in [
+            &data[..NUMBER_SIZE],
+            &data[..data.len() - 1],
+            &[0, 0, 0, 0, 9, 9][..],
+        ] {
+            assert!(matches!(
+                decode_did_cell(data),
+                Err(AppError::MoleculeError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn unknown_fields_are_kept() {
+        let cbor = serde_ipld_dagcbor::to_vec(&serde_json::json!({
+            "verificationMethods": {"atproto": "did:key:zQ3sh"},
+            "alsoKnownAs": ["at://alice.example.com"],
+            "services": {
+                "atproto_pds": {
+                    "type": "AtprotoPersonalDataServer",
+                    "endpoint": "https://pds.example.com",
+                    "priority": 1
+                }
+            },
+            "controller": "did:web5:abc"
+        }))
+        .unwrap();
+        let document = serde_json::to_value(parse_document(&cbor).unwrap()).unwrap();
+        assert_eq!(document["services"]["atproto_pds"]["priority"], 1);
+        assert_eq!(document["controller"], "did:web5:abc");
+    }
+
+    #[test]
+    fn bytes_and_links_use_the_atproto_convention() {
+        let cid = Cid::try_from(document_cid(b"doc").as_str()).unwrap();
+        let ipld = Ipld::List(vec![Ipld::Bytes(vec![1, 2, 3]), Ipld::Link(cid)]);
+        assert_eq!(
+            ipld_to_json(ipld),
+            serde_json::json!([{"$bytes": "AQID"}, {"$link": cid.to_string()}])
+        );
+    }
+}
@@ -16,14 +16,26 @@ pub enum AppError {
     DidDocNotValid(String),
     #[display("Db execution failed: {_0}")]
     DbExecuteFailed(String),
+    #[display("Db connection failed: {_0}")]
+    DbConnectFailed(String),
+    #[display("Db connection pool timed out")]
+    DbPoolTimeout,
     #[display("Runtime Internal Error: {_0}")]
     RunTimeError(String),
     #[display("Molecule decode Error: {_0}")]
     MoleculeError(String),
+    #[display("Did data version not supported: {_0}")]
+    UnknownDidDataVersion(u32),
     #[display("Dag-cbor decode Error: {_0}")]
     DagCborError(String),
-    #[display("Did document in compatible: {_0}")]
-    IncompatibleDidDoc(String),
+    #[display("Did document alsoKnownAs incompatible: {_0}")]
+    IncompatibleAlsoKnownAs(String),
+    #[display("Did document services incompatible: {_0}")]
+    IncompatibleServices(String),
+    #[display("Did document signing key incompatible: {_0}")]
+    IncompatibleSigningKey(String),
+    #[display("Did document verificationMethods incompatible: {_0}")]
+    IncompatibleVerificationMethods(String),
     #[display("Did format in compatible: {_0}")]
     IncompatibleDid(String),
     #[display("Db record count in compatible: {_0}")]
@@ -32,6 +44,65 @@ pub enum AppError {
     CkbRpcError(String),
     #[display("Handle not registered: {_0}")]
     HandleNotFound(String),
+    #[display("Local id not registered: {_0}")]
+    LocalIdNotFound(String),
+    #[display("Did or handle already registered: {_0}")]
+    RecordConflict(String),
+    #[display("Batch too large, at most {_0} items are allowed")]
+    BatchTooLarge(usize),
+    #[display("Invalid cursor: {_0}")]
+    InvalidCursor(String),
+    #[display("Query too short, at least {_0} characters are required")]
+    QueryTooShort(usize),
+    #[display("Too many requests")]
+    RateLimited,
+    #[display("Unauthorized")]
+    Unauthorized,
+    #[display("Invalid webhook: {_0}")]
+    InvalidWebhook(String),
+    #[display("Webhook not found: {_0}")]
+    WebhookNotFound(i32),
+    #[display("Webhook delivery not found: {_0}")]
+    DeliveryNotFound(i64),
+    #[display("Config error: {_0}")]
+    ConfigError(String),
+}
+
+impl AppError {
+    pub fn category(&self) -> &'static str {
+        match self {
+            AppError::DidDocNotFound(_) => "did_doc_not_found",
+            AppError::CountNotFound => "count_not_found",
+            AppError::DidDocNoData(_) => "did_doc_no_data",
+            AppError::DidDocNotValid(_) => "did_doc_not_valid",
+            AppError::DbExecuteFailed(_) => "db_execute_failed",
+            AppError::DbConnectFailed(_) => "db_connect_failed",
+            AppError::DbPoolTimeout => "db_pool_timeout",
+            AppError::RunTimeError(_) => "runtime_error",
+            AppError::MoleculeError(_) => "molecule_error",
+            AppError::UnknownDidDataVersion(_) => "unknown_did_data_version",
+            AppError::DagCborError(_) => "dag_cbor_error",
+            AppError::IncompatibleAlsoKnownAs(_) => "incompatible_also_known_as",
+            AppError::IncompatibleServices(_) => "incompatible_services",
+            AppError::IncompatibleSigningKey(_) => "incompatible_signing_key",
+            AppError::IncompatibleVerificationMethods(_) => "incompatible_verification_methods",
+            AppError::IncompatibleDid(_) => "incompatible_did",
+            AppError::DbCountError(_) => "db_count_error",
+            AppError::CkbRpcError(_) => "ckb_rpc_error",
+            AppError::HandleNotFound(_) => "handle_not_found",
+            AppError::LocalIdNotFound(_) => "local_id_not_found",
+            AppError::RecordConflict(_) => "record_conflict",
+            AppError::BatchTooLarge(_) => "batch_too_large",
+            AppError::InvalidCursor(_) => "invalid_cursor",
+            AppError::QueryTooShort(_) => "query_too_short",
+            AppError::RateLimited => "rate_limited",
+            AppError::Unauthorized => "unauthorized",
+            AppError::InvalidWebhook(_) => "invalid_webhook",
+            AppError::WebhookNotFound(_) => "webhook_not_found",
+            AppError::DeliveryNotFound(_) => "delivery_not_found",
+            AppError::ConfigError(_) => "config_error",
+        }
+    }
 }
 
 impl ResponseError for AppError {
@@ -41,15 +112,32 @@ impl ResponseError for AppError {
             AppError::DidDocNoData(_) => (404, self.to_string()),
             AppError::DidDocNotValid(_) => (400, self.to_string()),
             AppError::DbExecuteFailed(_) => (500, self.to_string()),
+            AppError::DbConnectFailed(_) => (503, self.to_string()),
+            AppError::DbPoolTimeout => (503, self.to_string()),
             AppError::RunTimeError(_) => (500, self.to_string()),
             AppError::MoleculeError(_) => (500, self.to_string()),
+            AppError::UnknownDidDataVersion(_) => (500, self.to_string()),
             AppError::DagCborError(_) => (500, self.to_string()),
-            AppError::IncompatibleDidDoc(_) => (500, self.to_string()),
+            AppError::IncompatibleAlsoKnownAs(_) => (500, self.to_string()),
+            AppError::IncompatibleServices(_) => (500, self.to_string()),
+            AppError::IncompatibleSigningKey(_) => (500, self.to_string()),
+            AppError::IncompatibleVerificationMethods(_) => (500, self.to_string()),
             AppError::CountNotFound => (500, self.to_string()),
             AppError::DbCountError(_) => (500, self.to_string()),
             AppError::CkbRpcError(_) => (500, self.to_string()),
             AppError::HandleNotFound(_) => (404, self.to_string()),
             AppError::IncompatibleDid(_) => (500, self.to_string()),
+            AppError::LocalIdNotFound(_) => (404, self.to_string()),
+            AppError::RecordConflict(_) => (409, self.to_string()),
+            AppError::BatchTooLarge(_) => (400, self.to_string()),
+            AppError::InvalidCursor(_) => (400, self.to_string()),
+            AppError::QueryTooShort(_) => (400, self.to_string()),
+            AppError::RateLimited => (429, self.to_string()),
+            AppError::Unauthorized => (401, self.to_string()),
+            AppError::InvalidWebhook(_) => (400, self.to_string()),
+            AppError::WebhookNotFound(_) => (404, self.to_string()),
+            AppError::DeliveryNotFound(_) => (404, self.to_string()),
+            AppError::ConfigError(_) => (500, self.to_string()),
         };
         let error_response = ErrorResponse { message: error_msg };
 
@@ -68,3 +156,9 @@ impl From<io::Error> for AppError {
         AppError::RunTimeError(value.to_string())
     }
 }
+
+impl From<diesel::result::Error> for AppError {
+    fn from(value: diesel::result::Error) -> Self {
+        AppError::DbExecuteFailed(value.to_string())
+    }
+}
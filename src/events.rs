@@ -0,0 +1,207 @@
+use crate::{
+    db::{DbPool, interact, query_did_events, query_last_event_seq},
+    error::AppError,
+    models::DidEvent,
+};
+use std::collections::VecDeque;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Events a subscriber may fall behind the indexer before it has to catch up
+/// from the event log.
+const BUS_CAPACITY: usize = 1024;
+const REPLAY_PAGE: i64 = 500;
+
+/// What happened to a did in one transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A record was created for a did that had none.
+    Create,
+    /// The record of a did was replaced in the same transaction.
+    Update,
+    /// The record of a did was replaced by one with another handle.
+    HandleChange,
+    /// The record of a did was removed.
+    Deactivate,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Create => "create",
+            EventKind::Update => "update",
+            EventKind::HandleChange => "handle_change",
+            EventKind::Deactivate => "deactivate",
+        }
+    }
+
+    /// Kind of replacing a record holding `previous_handle` by one holding `handle`.
+    pub fn replacement(previous_handle: &Option<String>, handle: &Option<String>) -> Self {
+        if previous_handle == handle {
+            EventKind::Update
+        } else {
+            EventKind::HandleChange
+        }
+    }
+}
+
+/// Hands the events the indexer logs to the subscribers in this process.
+pub struct EventBus {
+    sender: broadcast::Sender<DidEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus {
+            sender: broadcast::channel(BUS_CAPACITY).0,
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: DidEvent) {
+        // Nobody listening is fine.
+        let _ = self.sender.send(event);
+    }
+
+    /// Live events only, for consumers that read what they need from the
+    /// database and just want to be woken up.
+    pub fn subscribe(&self) -> broadcast::Receiver<DidEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Ordered event stream of one subscriber: the event log after `cursor`, then
+/// live events. A subscriber that falls behind the bus catches up from the log,
+/// so every event is delivered once and in sequence order.
+pub struct EventCursor {
+    pool: DbPool,
+    receiver: broadcast::Receiver<DidEvent>,
+    last_seq: i64,
+    backlog: VecDeque<DidEvent>,
+    replaying: bool,
+}
+
+impl EventCursor {
+    /// Start after `cursor`, or after the latest event without one. `pool`
+    /// must be the primary, a lagging replica would leave gaps.
+    pub async fn new(pool: DbPool, bus: &EventBus, cursor: Option<i64>) -> Result<Self, AppError> {
+        // Subscribe first, so that nothing published while the start is
+        // looked up is missed.
+        let receiver = bus.sender.subscribe();
+        let last_seq = match cursor {
+            Some(cursor) => cursor,
+            None => interact(&pool, query_last_event_seq).await?,
+        };
+        Ok(EventCursor {
+            pool,
+            receiver,
+            last_seq,
+            backlog: VecDeque::new(),
+            replaying: cursor.is_some(),
+        })
+    }
+
+    /// Next event. Cancel safe.
+    pub async fn next(&mut self) -> Result<DidEvent, AppError> {
+        loop {
+            if let Some(event) = self.backlog.pop_front() {
+                self.last_seq = event.seq;
+                return Ok(event);
+            }
+            if self.replaying {
+                let after = self.last_seq;
+                let page = interact(&self.pool, move |conn| {
+                    query_did_events(conn, after, REPLAY_PAGE)
+                })
+                .await?;
+                self.replaying = page.len() as i64 == REPLAY_PAGE;
+                self.backlog.extend(page);
+                continue;
+            }
+            match self.receiver.recv().await {
+                Ok(event) if event.seq > self.last_seq => {
+                    self.last_seq = event.seq;
+                    return Ok(event);
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("event subscriber lagged by {skipped}, replaying from the log");
+                    self.replaying = true;
+                }
+                Err(RecvError::Closed) => {
+                    return Err(AppError::RunTimeError("event bus closed".to_string()));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{establish_connection, insert_did_event, run_migrations},
+        models::NewDidEvent,
+    };
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// Log `count` events and publish them, as the indexer does on commit.
+    async fn log_events(pool: &DbPool, bus: &EventBus, count: usize) {
+        let events = interact(pool, move |conn| {
+            (0..count)
+                .map(|_| {
+                    let event = NewDidEvent {
+                        did: "did:web5:zxg43tonzxg43tonzxg43tonzxg43ton".to_string(),
+                        kind: EventKind::Update.as_str().to_string(),
+                        ..Default::default()
+                    };
+                    insert_did_event(conn, &event)
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await
+        .unwrap();
+        for event in events {
+            bus.publish(event);
+        }
+    }
+
+    async fn next_seqs(cursor: &mut EventCursor, count: usize) -> Vec<i64> {
+        let mut seqs = Vec::with_capacity(count);
+        for _ in 0..count {
+            let event = timeout(Duration::from_secs(5), cursor.next()).await;
+            seqs.push(event.unwrap().unwrap().seq);
+        }
+        seqs
+    }
+
+    #[actix_web::test]
+    async fn lagged_subscriber_replays_from_the_log() {
+        let pool =
+            establish_connection("sqlite://:memory:".into(), 1, Duration::from_secs(5)).unwrap();
+        interact(&pool, run_migrations).await.unwrap();
+        let bus = EventBus::default();
+        let mut cursor = EventCursor::new(pool.clone(), &bus, None).await.unwrap();
+
+        // Overflow the bus by more than a replay page.
+        let lagged = BUS_CAPACITY + REPLAY_PAGE as usize + 10;
+        log_events(&pool, &bus, lagged).await;
+        let mut seqs = next_seqs(&mut cursor, REPLAY_PAGE as usize + 100).await;
+        // Events logged while replaying are both in the log and on the bus.
+        log_events(&pool, &bus, 5).await;
+        seqs.extend(next_seqs(&mut cursor, lagged + 5 - seqs.len()).await);
+        // Live again: the replayed events still queued on the bus are skipped.
+        log_events(&pool, &bus, 3).await;
+        seqs.extend(next_seqs(&mut cursor, 3).await);
+
+        let expected: Vec<i64> = (1..=(lagged + 8) as i64).collect();
+        assert_eq!(seqs, expected);
+        assert!(!cursor.replaying);
+        assert!(
+            timeout(Duration::from_millis(100), cursor.next())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,9 +1,21 @@
 use crate::{
-    ckb::CkbCtx,
-    config::AppConfig,
-    db::{establish_connection, query_count},
+    cache::ResolutionCache,
+    ckb::{CkbCtx, reindex},
+    config::{AppConfig, Cli, Command},
+    db::{establish_connection, interact, prepare_schema, query_resume_height, upsert_webhook},
     error::AppError,
-    router::query_did_doc,
+    events::EventBus,
+    mempool::{MempoolWatcher, PendingHandles},
+    rate_limit::RateLimiter,
+    replica::ReadPools,
+    router::{
+        create_webhook, list_deliveries, list_dids, list_webhooks, query_address_history,
+        query_did_doc, query_did_doc_raw, query_events, query_handle_available,
+        query_handle_history, query_handle_search, query_invalid, query_metrics, query_status,
+        query_version, remove_webhook, resolve_bulk, resolve_local_id, retry_webhook_delivery,
+        subscribe,
+    },
+    webhook::WebhookWorker,
 };
 use actix_cors::Cors;
 use actix_files::NamedFile;
@@ -13,8 +25,8 @@ use actix_web::{
     middleware, web,
 };
 use ckb_sdk::{CkbRpcAsyncClient, NetworkType};
-use ckb_types::H256;
-use std::str::FromStr;
+use clap::Parser;
+use std::{str::FromStr, time::Duration};
 use tokio::{select, signal::ctrl_c, task};
 use tokio_util::sync::CancellationToken;
 use tracing::Level;
@@ -37,32 +49,125 @@ async fn default_handler(req_method: Method) -> Result<impl Responder> {
 
 #[actix_web::main]
 async fn main() -> Result<(), AppError> {
-    let config = AppConfig::from_env();
+    let cli = Cli::parse();
+    let config = AppConfig::from_env()?;
     let log_level = Level::from_str(&config.log_level).unwrap();
     let subscriber = FmtSubscriber::builder().with_max_level(log_level).finish();
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
-    info!("Config: {config:?}");
+    info!("Config: {:?}", config.redacted());
+
+    let pool = establish_connection(
+        config.data_base_url.clone(),
+        config.db_pool_size as usize,
+        Duration::from_secs(config.db_pool_timeout),
+    )?;
+    let no_migrate = cli.no_migrate;
+    let command = cli.command;
+    let profile = config.validation_profile;
+    let exit = interact(&pool, move |conn| {
+        prepare_schema(
+            conn,
+            !no_migrate || matches!(command, Some(Command::Migrate)),
+        )?;
+        match command {
+            Some(Command::Migrate) => Ok(true),
+            Some(Command::Reindex) => reindex(conn, profile).map(|_| true),
+            None => Ok(false),
+        }
+    })
+    .await?;
+    if exit {
+        return Ok(());
+    }
+    let mut replicas = Vec::with_capacity(config.data_base_read_urls.len());
+    for read_url in &config.data_base_read_urls {
+        let replica = establish_connection(
+            read_url.clone(),
+            config.db_pool_size as usize,
+            Duration::from_secs(config.db_pool_timeout),
+        )?;
+        replicas.push((read_url.clone(), replica));
+    }
+    let read_pools = web::Data::new(ReadPools::new(
+        pool.clone(),
+        replicas,
+        config.replica_max_lag,
+    ));
+
+    let cache = web::Data::new(ResolutionCache::new(
+        config.cache_size as usize,
+        Duration::from_secs(config.cache_ttl),
+    ));
+
+    let app_config = web::Data::new(config.clone());
+    let search_limiter = web::Data::new(RateLimiter::new(
+        config.search_rate_limit as u32,
+        config.trusted_proxy,
+    ));
+    let json_limit = (config.resolve_max_batch as usize * 512).max(32 * 1024);
+
+    let events = web::Data::new(EventBus::default());
+
+    if let Some(secret) = &config.webhook_secret {
+        let (webhooks, secret) = (config.webhooks.clone(), secret.0.clone());
+        interact(&pool, move |conn| {
+            for url in webhooks {
+                let webhook = upsert_webhook(conn, url, secret.clone())?;
+                info!("Webhook #{}: {}", webhook.id, webhook.url);
+            }
+            Ok(())
+        })
+        .await?;
+    }
 
-    let pool = establish_connection(config.data_base_url);
     let token = CancellationToken::new();
-    let pool_for_rolling = pool.clone();
-    let mut conn = pool_for_rolling.get().unwrap();
-    let mut ckb_ctx = CkbCtx::init(&mut conn, token).await;
+    let pool_for_rolling = pool;
+    let mut ckb_ctx = CkbCtx::init(
+        &pool_for_rolling,
+        token,
+        config.validation_profile,
+        cache.clone().into_inner(),
+        events.clone().into_inner(),
+    )
+    .await?;
+
+    let watch_pools = read_pools.clone();
+    let watch_token = ckb_ctx.token.clone();
+    task::spawn(async move { watch_pools.watch(watch_token).await });
+
+    let pending_handles = web::Data::new(PendingHandles::default());
+    if config.mempool_poll_interval > 0 {
+        let watcher = MempoolWatcher::new(
+            &config.ckb_node,
+            NetworkType::from_raw_str(&config.ckb_network)
+                .expect("Config CKB_NETWORK set 'ckb' or 'ckb_testnet'"),
+            config.deployments.clone(),
+            config.validation_profile,
+            pending_handles.clone().into_inner(),
+        );
+        let interval = Duration::from_secs(config.mempool_poll_interval);
+        let mempool_token = ckb_ctx.token.clone();
+        task::spawn(async move { watcher.watch(interval, mempool_token).await });
+    }
+
+    let webhook_worker = WebhookWorker::new(pool_for_rolling.clone(), config.webhook_max_attempts);
+    let webhook_events = events.clone().into_inner();
+    let webhook_token = ckb_ctx.token.clone();
+    task::spawn(async move { webhook_worker.run(webhook_events, webhook_token).await });
 
     let task_handle = task::spawn(async move {
-        let target_code_hash = H256::from_str(&config.code_hash).unwrap();
         let client = CkbRpcAsyncClient::new(&config.ckb_node);
         let mut is_sync = true;
         let start_height = config.start_height;
-        let mut height = match query_count(&mut conn) {
-            Ok(count) => {
-                if count < start_height as i64 {
+        let mut height = match interact(&pool_for_rolling, query_resume_height).await {
+            Ok(resume) => {
+                if resume < start_height as i64 {
                     return Err(AppError::DbCountError(format!(
-                        "height error. count: {count}, config height: {start_height}",
+                        "height error. resume height: {resume}, config height: {start_height}",
                     )));
                 } else {
-                    info!("Found old count record: {count}");
-                    count as u64
+                    info!("Resume from: {resume}");
+                    resume as u64
                 }
             }
             Err(AppError::CountNotFound) => {
@@ -89,10 +194,10 @@ async fn main() -> Result<(), AppError> {
                 res = ckb_ctx.rolling(
                     height,
                     &client,
-                    &mut conn,
+                    &pool_for_rolling,
                     NetworkType::from_raw_str(&config.ckb_network)
                         .expect("Config CKB_NETWORK set 'ckb' or 'ckb_testnet'"),
-                    target_code_hash.clone(),
+                    &config.deployments,
                     is_sync,
                 ) => {
                     match res {
@@ -121,15 +226,58 @@ async fn main() -> Result<(), AppError> {
 
     let server = HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(pool.clone()))
+            .app_data(read_pools.clone())
+            .app_data(cache.clone())
+            .app_data(app_config.clone())
+            .app_data(search_limiter.clone())
+            .app_data(events.clone())
+            .app_data(pending_handles.clone())
+            .app_data(web::JsonConfig::default().limit(json_limit))
             .wrap(middleware::Compress::default())
             .wrap(middleware::Logger::default().log_target("@"))
             .wrap(
                 Cors::default()
-                    .allowed_methods(vec!["GET"])
+                    .allowed_methods(vec!["GET", "POST", "DELETE"])
                     .supports_credentials()
                     .max_age(3600),
             )
+            .service(web::resource("/local-id/{local_id}").route(web::get().to(resolve_local_id)))
+            .service(web::resource("/dids").route(web::get().to(list_dids)))
+            .service(web::resource("/subscribe").route(web::get().to(subscribe)))
+            .service(web::resource("/events").route(web::get().to(query_events)))
+            .service(web::resource("/search/handles").route(web::get().to(query_handle_search)))
+            .service(
+                web::resource("/handle/{handle}/available")
+                    .route(web::get().to(query_handle_available)),
+            )
+            .service(
+                web::resource("/handle/{handle}/history")
+                    .route(web::get().to(query_handle_history)),
+            )
+            .service(
+                web::resource("/address/{address}/history")
+                    .route(web::get().to(query_address_history)),
+            )
+            .service(web::resource("/invalid").route(web::get().to(query_invalid)))
+            .service(web::resource("/version").route(web::get().to(query_version)))
+            .service(web::resource("/metrics").route(web::get().to(query_metrics)))
+            .service(web::resource("/resolve").route(web::post().to(resolve_bulk)))
+            .service(
+                web::resource("/admin/webhooks")
+                    .route(web::get().to(list_webhooks))
+                    .route(web::post().to(create_webhook)),
+            )
+            .service(web::resource("/admin/webhooks/{id}").route(web::delete().to(remove_webhook)))
+            .service(
+                web::resource("/admin/webhooks/{id}/deliveries")
+                    .route(web::get().to(list_deliveries)),
+            )
+            .service(
+                web::resource("/admin/deliveries/{id}/retry")
+                    .route(web::post().to(retry_webhook_delivery)),
+            )
+            .service(web::resource("/{did}/status").route(web::get().to(query_status)))
+            .service(web::resource("/{did}/raw").route(web::get().to(query_did_doc_raw)))
             .service(web::resource("/{did}").route(web::get().to(query_did_doc)))
             // .service(web::resource("/resolve-handle/{handle}").route(web::get().to(resolve_handle)))
             .service(
@@ -155,13 +303,21 @@ async fn main() -> Result<(), AppError> {
     Ok(())
 }
 
+pub mod cache;
 mod cell_data;
 mod ckb;
 pub mod config;
 pub mod db;
+mod decoder;
 pub mod error;
+pub mod events;
+pub mod mempool;
 pub mod models;
+pub mod rate_limit;
+pub mod replica;
 pub mod router;
 pub mod schema;
+pub mod sql_types;
 pub mod types;
 pub mod util;
+pub mod webhook;
@@ -0,0 +1,193 @@
+use crate::{
+    ckb::did_record_from_cell,
+    config::{ContractDeployment, ValidationProfile},
+    error::AppError,
+    models::DidCell,
+    util::{calculate_address, calculate_web5_did, normalize_handle},
+};
+use ckb_jsonrpc_types::{Either, RawTxPool, TransactionView};
+use ckb_sdk::{CkbRpcAsyncClient, NetworkType};
+use ckb_types::H256;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+/// Pool transactions fetched per round, so a full pool is read over several
+/// rounds instead of in one burst of RPC calls.
+const FETCH_PER_ROUND: usize = 200;
+
+/// A handle claimed by a did cell of a transaction in the node's pool.
+#[derive(Debug, Clone)]
+pub struct PendingClaim {
+    pub did: String,
+    pub tx_hash: String,
+}
+
+/// Handles claimed by the transactions the CKB node holds in its pool, by
+/// normalized handle.
+#[derive(Default)]
+pub struct PendingHandles {
+    claims: Mutex<HashMap<String, PendingClaim>>,
+}
+
+impl PendingHandles {
+    pub fn get(&self, handle: &str) -> Option<PendingClaim> {
+        self.claims.lock().unwrap().get(handle).cloned()
+    }
+
+    pub(crate) fn replace(&self, claims: HashMap<String, PendingClaim>) {
+        *self.claims.lock().unwrap() = claims;
+    }
+}
+
+/// Polls the node's transaction pool for did cells that are not indexed yet.
+pub struct MempoolWatcher {
+    client: CkbRpcAsyncClient,
+    network: NetworkType,
+    deployments: Vec<ContractDeployment>,
+    profile: ValidationProfile,
+    pending: Arc<PendingHandles>,
+    /// Claims of every pool transaction read so far; empty for those without
+    /// did cells.
+    seen: HashMap<H256, Vec<(String, PendingClaim)>>,
+}
+
+impl MempoolWatcher {
+    pub fn new(
+        ckb_node: &str,
+        network: NetworkType,
+        deployments: Vec<ContractDeployment>,
+        profile: ValidationProfile,
+        pending: Arc<PendingHandles>,
+    ) -> Self {
+        MempoolWatcher {
+            client: CkbRpcAsyncClient::new(ckb_node),
+            network,
+            deployments,
+            profile,
+            pending,
+            seen: HashMap::new(),
+        }
+    }
+
+    pub async fn watch(mut self, interval: Duration, token: CancellationToken) {
+        loop {
+            if let Err(app_err) = self.poll().await {
+                warn!("mempool poll failed: {app_err}");
+            }
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = time::sleep(interval) => {},
+            }
+        }
+    }
+
+    async fn poll(&mut self) -> Result<(), AppError> {
+        let pool: HashSet<H256> = match self
+            .client
+            .get_raw_tx_pool(Some(false))
+            .await
+            .map_err(|e| AppError::CkbRpcError(e.to_string()))?
+        {
+            RawTxPool::Ids(ids) => ids.pending.into_iter().chain(ids.proposed).collect(),
+            RawTxPool::Verbose(entries) => entries
+                .pending
+                .into_keys()
+                .chain(entries.proposed.into_keys())
+                .collect(),
+        };
+        // Committed or dropped, committed claims are live records by now.
+        self.seen.retain(|hash, _| pool.contains(hash));
+        let tip = self
+            .client
+            .get_tip_block_number()
+            .await
+            .map_err(|e| AppError::CkbRpcError(e.to_string()))?
+            .value();
+        let unseen: Vec<H256> = pool
+            .into_iter()
+            .filter(|hash| !self.seen.contains_key(hash))
+            .take(FETCH_PER_ROUND)
+            .collect();
+        for hash in unseen {
+            // Left unseen, so it is fetched again next round.
+            let res = match self.client.get_transaction(hash.clone()).await {
+                Ok(res) => res,
+                Err(e) => {
+                    warn!("mempool get_transaction {hash} failed: {e}");
+                    continue;
+                }
+            };
+            let tx = res
+                .and_then(|res| res.transaction)
+                .and_then(|tx| match tx.inner {
+                    Either::Left(tx) => Some(tx),
+                    Either::Right(_) => None,
+                });
+            let claims = match tx {
+                Some(tx) => self.claims(tx, tip + 1),
+                None => Vec::new(),
+            };
+            self.seen.insert(hash, claims);
+        }
+        self.pending.replace(
+            self.seen
+                .values()
+                .flatten()
+                .map(|(handle, claim)| (handle.clone(), claim.clone()))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    /// Handles the did cells of `tx` would register if it were committed at
+    /// `height`.
+    fn claims(&self, tx: TransactionView, height: u64) -> Vec<(String, PendingClaim)> {
+        let mut claims = Vec::new();
+        for (out_index, output) in tx.inner.outputs.into_iter().enumerate() {
+            let Some(type_script) = output.type_ else {
+                continue;
+            };
+            if !self
+                .deployments
+                .iter()
+                .any(|d| d.matches(&type_script, height))
+            {
+                continue;
+            }
+            let args = type_script.args.as_bytes();
+            let Some(cell_data) = tx.inner.outputs_data.get(out_index) else {
+                continue;
+            };
+            if args.len() < 20 {
+                continue;
+            }
+            let cell = DidCell {
+                tx_hash: tx.hash.to_string(),
+                out_index: out_index as i32,
+                did: calculate_web5_did(&args[..20]),
+                ckb_address: calculate_address(&output.lock.into(), self.network).to_string(),
+                cell_data: cell_data.as_bytes().to_vec(),
+                height: height as i64,
+                ..Default::default()
+            };
+            // Cells the indexer would reject don't claim anything.
+            if let Ok(record) = did_record_from_cell(&cell, self.profile)
+                && let Some(handle) = record.handle
+            {
+                claims.push((
+                    normalize_handle(&handle),
+                    PendingClaim {
+                        did: record.did,
+                        tx_hash: record.tx_hash,
+                    },
+                ));
+            }
+        }
+        claims
+    }
+}
@@ -3,6 +3,7 @@
 #![allow(unused)]
 #![allow(clippy::all)]
 
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde_derive::{Deserialize, Serialize};
 
@@ -11,22 +12,285 @@ use serde_derive::{Deserialize, Serialize};
 )]
 #[diesel(primary_key(did))]
 #[diesel(table_name = crate::schema::indexer::did_record)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
 #[serde(rename_all = "camelCase")]
 pub struct DidRecord {
     pub did: String,
     #[diesel(column_name = "ckbAddress")]
     pub ckb_address: String,
-    pub handle: String,
+    pub handle: Option<String>,
     #[diesel(column_name = "signingKey")]
-    pub signing_key: String,
+    pub signing_key: Option<String>,
     #[diesel(column_name = "txHash")]
     pub tx_hash: String,
     #[diesel(column_name = "outIndex")]
     pub out_index: i32,
-    pub document: String,
+    pub document: serde_json::Value,
     pub height: i64,
     #[diesel(column_name = "createdAt")]
-    pub created_at: String,
+    #[serde(serialize_with = "crate::util::serialize_time")]
+    pub created_at: DateTime<Utc>,
     pub valid: bool,
+    pub deployment: String,
+    #[diesel(column_name = "localId")]
+    pub local_id: Option<String>,
+    #[diesel(column_name = "cellData")]
+    #[serde(skip)]
+    pub cell_data: Option<Vec<u8>>,
+    #[diesel(column_name = "pdsEndpoint")]
+    pub pds_endpoint: Option<String>,
+    #[diesel(column_name = "serviceErrors")]
+    pub service_errors: serde_json::Value,
+    #[diesel(column_name = "documentCbor")]
+    #[serde(skip)]
+    pub document_cbor: Option<Vec<u8>>,
+    #[diesel(column_name = "documentCid")]
+    pub document_cid: Option<String>,
+    #[diesel(column_name = "blockTimestamp")]
+    pub block_timestamp: i64,
+}
+
+/// Listing entry of a did record, without its document.
+#[derive(Queryable, Selectable, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::indexer::did_record)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[serde(rename_all = "camelCase")]
+pub struct DidSummary {
+    pub did: String,
+    #[diesel(column_name = "ckbAddress")]
+    pub ckb_address: String,
+    pub handle: Option<String>,
+    #[diesel(column_name = "txHash")]
+    pub tx_hash: String,
+    #[diesel(column_name = "outIndex")]
+    pub out_index: i32,
+    pub height: i64,
+    #[diesel(column_name = "createdAt")]
+    #[serde(serialize_with = "crate::util::serialize_time")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, AsChangeset, Clone, Debug, PartialEq, Default)]
+#[diesel(table_name = crate::schema::indexer::did_record)]
+pub struct NewDidRecord {
+    pub did: String,
+    #[diesel(column_name = "ckbAddress")]
+    pub ckb_address: String,
+    pub handle: Option<String>,
+    #[diesel(column_name = "signingKey")]
+    pub signing_key: Option<String>,
+    #[diesel(column_name = "txHash")]
+    pub tx_hash: String,
+    #[diesel(column_name = "outIndex")]
+    pub out_index: i32,
+    pub document: serde_json::Value,
+    pub height: i64,
+    #[diesel(column_name = "createdAt")]
+    #[diesel(serialize_as = crate::sql_types::UtcTimestamp)]
+    pub created_at: DateTime<Utc>,
+    pub valid: bool,
+    pub deployment: String,
+    #[diesel(column_name = "localId")]
+    pub local_id: Option<String>,
+    #[diesel(column_name = "cellData")]
+    pub cell_data: Option<Vec<u8>>,
+    #[diesel(column_name = "pdsEndpoint")]
+    pub pds_endpoint: Option<String>,
+    #[diesel(column_name = "serviceErrors")]
+    pub service_errors: serde_json::Value,
+    #[diesel(column_name = "documentCbor")]
+    pub document_cbor: Option<Vec<u8>>,
+    #[diesel(column_name = "documentCid")]
+    pub document_cid: Option<String>,
+    #[diesel(column_name = "blockTimestamp")]
+    pub block_timestamp: i64,
+}
+
+#[derive(
+    Queryable, Selectable, Insertable, Clone, Debug, PartialEq, Default, Serialize, Deserialize,
+)]
+#[diesel(table_name = crate::schema::indexer::did_delete_record)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[serde(rename_all = "camelCase")]
+pub struct DidDeleteRecord {
+    pub did: String,
+    #[diesel(column_name = "ckbAddress")]
+    pub ckb_address: String,
+    pub handle: Option<String>,
+    #[diesel(column_name = "signingKey")]
+    pub signing_key: Option<String>,
+    #[diesel(column_name = "txHash")]
+    pub tx_hash: String,
+    #[diesel(column_name = "inIndex")]
+    pub in_index: i32,
+    pub document: serde_json::Value,
+    pub height: i64,
+    #[diesel(column_name = "deletedAt")]
+    #[diesel(serialize_as = crate::sql_types::UtcTimestamp)]
+    #[serde(serialize_with = "crate::util::serialize_time")]
+    pub deleted_at: DateTime<Utc>,
+    pub deployment: String,
+    #[diesel(column_name = "blockTimestamp")]
+    pub block_timestamp: i64,
+}
+
+#[derive(
+    Queryable, Selectable, Insertable, Clone, Debug, PartialEq, Default, Serialize, Deserialize,
+)]
+#[diesel(table_name = crate::schema::indexer::did_cell)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[serde(rename_all = "camelCase")]
+pub struct DidCell {
+    #[diesel(column_name = "txHash")]
+    pub tx_hash: String,
+    #[diesel(column_name = "outIndex")]
+    pub out_index: i32,
+    pub did: String,
+    #[diesel(column_name = "ckbAddress")]
+    pub ckb_address: String,
+    pub deployment: String,
+    #[diesel(column_name = "cellData")]
+    pub cell_data: Vec<u8>,
+    pub height: i64,
+    #[diesel(column_name = "blockTimestamp")]
+    pub block_timestamp: i64,
+    #[diesel(column_name = "spentTxHash")]
+    pub spent_tx_hash: Option<String>,
+    #[diesel(column_name = "spentInIndex")]
+    pub spent_in_index: Option<i32>,
+    #[diesel(column_name = "spentHeight")]
+    pub spent_height: Option<i64>,
+}
+
+#[derive(
+    Queryable, Selectable, Insertable, Clone, Debug, PartialEq, Default, Serialize, Deserialize,
+)]
+#[diesel(table_name = crate::schema::indexer::invalid_cell)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[serde(rename_all = "camelCase")]
+pub struct InvalidCell {
+    #[diesel(column_name = "txHash")]
+    pub tx_hash: String,
+    #[diesel(column_name = "outIndex")]
+    pub out_index: i32,
+    pub did: String,
+    pub category: String,
+    pub reason: String,
+    pub height: i64,
+    #[diesel(column_name = "createdAt")]
+    #[diesel(serialize_as = crate::sql_types::UtcTimestamp)]
+    #[serde(serialize_with = "crate::util::serialize_time")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Entry of the identity event log. `tx_hash` and `index` are the outpoint of
+/// the new cell, or the input spending the old one for a `deactivate`.
+#[derive(Queryable, Selectable, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::indexer::did_event)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[serde(rename_all = "camelCase")]
+pub struct DidEvent {
+    pub seq: i64,
+    pub did: String,
+    pub kind: String,
+    pub handle: Option<String>,
+    #[diesel(column_name = "txHash")]
+    pub tx_hash: String,
+    pub index: i32,
+    pub height: i64,
+    #[diesel(column_name = "createdAt")]
+    #[serde(serialize_with = "crate::util::serialize_time")]
+    pub created_at: DateTime<Utc>,
+    /// Handle of the replaced record, for an `update`.
+    #[diesel(column_name = "previousHandle")]
+    pub previous_handle: Option<String>,
+}
+
+#[derive(Insertable, Clone, Debug, PartialEq, Default)]
+#[diesel(table_name = crate::schema::indexer::did_event)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct NewDidEvent {
+    pub did: String,
+    pub kind: String,
+    pub handle: Option<String>,
+    #[diesel(column_name = "txHash")]
+    pub tx_hash: String,
+    pub index: i32,
+    pub height: i64,
+    #[diesel(column_name = "createdAt")]
+    #[diesel(serialize_as = crate::sql_types::UtcTimestamp)]
+    pub created_at: DateTime<Utc>,
+    #[diesel(column_name = "previousHandle")]
+    pub previous_handle: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::indexer::webhook)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    #[diesel(column_name = "createdAt")]
+    #[serde(serialize_with = "crate::util::serialize_time")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable, Clone, Debug, PartialEq, Default)]
+#[diesel(table_name = crate::schema::indexer::webhook)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct NewWebhook {
+    pub url: String,
+    pub secret: String,
+    #[diesel(column_name = "createdAt")]
+    #[diesel(serialize_as = crate::sql_types::UtcTimestamp)]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Outbox entry: one event to be posted to one webhook.
+#[derive(Queryable, Selectable, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[diesel(table_name = crate::schema::indexer::webhook_delivery)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    pub id: i64,
+    #[diesel(column_name = "webhookId")]
+    pub webhook_id: i32,
+    #[diesel(column_name = "eventSeq")]
+    pub event_seq: i64,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    #[diesel(column_name = "nextAttemptAt")]
+    #[serde(serialize_with = "crate::util::serialize_time")]
+    pub next_attempt_at: DateTime<Utc>,
+    #[diesel(column_name = "lastError")]
+    pub last_error: Option<String>,
+    #[diesel(column_name = "createdAt")]
+    #[serde(serialize_with = "crate::util::serialize_time")]
+    pub created_at: DateTime<Utc>,
+    #[diesel(column_name = "deliveredAt")]
+    #[serde(serialize_with = "crate::util::serialize_opt_time")]
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable, Clone, Debug, PartialEq, Default)]
+#[diesel(table_name = crate::schema::indexer::webhook_delivery)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct NewWebhookDelivery {
+    #[diesel(column_name = "webhookId")]
+    pub webhook_id: i32,
+    #[diesel(column_name = "eventSeq")]
+    pub event_seq: i64,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    #[diesel(column_name = "nextAttemptAt")]
+    #[diesel(serialize_as = crate::sql_types::UtcTimestamp)]
+    pub next_attempt_at: DateTime<Utc>,
+    #[diesel(column_name = "createdAt")]
+    #[diesel(serialize_as = crate::sql_types::UtcTimestamp)]
+    pub created_at: DateTime<Utc>,
 }
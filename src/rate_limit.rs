@@ -0,0 +1,117 @@
+use actix_web::HttpRequest;
+use lru::LruCache;
+use std::{
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::Instant,
+};
+
+/// Clients tracked before the least recently seen one is forgotten.
+const MAX_TRACKED: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+
+/// Per client token bucket: `per_minute` requests a minute, in bursts of up
+/// to `per_minute`.
+pub struct RateLimiter {
+    per_minute: u32,
+    trust_proxy: bool,
+    buckets: Mutex<LruCache<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    /// A `per_minute` of 0 disables the limit. With `trust_proxy` clients are
+    /// told apart by the `Forwarded` / `X-Forwarded-For` address, which only a
+    /// reverse proxy in front of the indexer can be trusted to set.
+    pub fn new(per_minute: u32, trust_proxy: bool) -> Self {
+        RateLimiter {
+            per_minute,
+            trust_proxy,
+            buckets: Mutex::new(LruCache::new(MAX_TRACKED)),
+        }
+    }
+
+    /// Address the requests of `req` are counted against.
+    pub fn client(&self, req: &HttpRequest) -> Option<IpAddr> {
+        if !self.trust_proxy {
+            return req.peer_addr().map(|peer| peer.ip());
+        }
+        let info = req.connection_info();
+        let addr = info.realip_remote_addr()?;
+        addr.parse::<IpAddr>()
+            .or_else(|_| addr.parse::<SocketAddr>().map(|addr| addr.ip()))
+            .ok()
+            .or_else(|| req.peer_addr().map(|peer| peer.ip()))
+    }
+
+    /// Take a token for `client`, false when it has none left.
+    pub fn check(&self, client: IpAddr) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+        let capacity = self.per_minute as f64;
+        let refill = |tokens: f64, since: Instant| {
+            (tokens + since.elapsed().as_secs_f64() * capacity / 60.0).min(capacity)
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let (tokens, last) = buckets.get_or_insert_mut(client, || (capacity, now));
+        *tokens = refill(*tokens, *last);
+        *last = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn forwarded_address_needs_a_trusted_proxy() {
+        let req = TestRequest::default()
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .insert_header(("x-forwarded-for", "203.0.113.7"))
+            .to_http_request();
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+        let forwarded: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(RateLimiter::new(1, false).client(&req), Some(peer));
+        assert_eq!(RateLimiter::new(1, true).client(&req), Some(forwarded));
+
+        let direct = TestRequest::default()
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .to_http_request();
+        assert_eq!(RateLimiter::new(1, true).client(&direct), Some(peer));
+    }
+
+    #[test]
+    fn buckets_are_per_client() {
+        let limiter = RateLimiter::new(2, false);
+        let (a, b) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        assert!(limiter.check(a));
+        assert!(limiter.check(a));
+        assert!(!limiter.check(a));
+        assert!(limiter.check(b));
+    }
+
+    #[test]
+    fn least_recently_seen_client_is_forgotten() {
+        let limiter = RateLimiter::new(1, false);
+        let throttled: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(limiter.check(throttled));
+        assert!(!limiter.check(throttled));
+        for i in 0..MAX_TRACKED.get() as u32 - 1 {
+            assert!(limiter.check(IpAddr::from((0x0b00_0000 + i).to_be_bytes())));
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_TRACKED.get());
+        assert!(!limiter.check(throttled));
+
+        // One client more pushes out the least recently seen one.
+        assert!(limiter.check("12.0.0.1".parse().unwrap()));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_TRACKED.get());
+        assert!(limiter.check("11.0.0.0".parse().unwrap()));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{
+    db::{DbPool, interact, query_sync_checkpoint},
+    error::AppError,
+    util::redact_url,
+};
+use std::{
+    sync::atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(3);
+
+struct Replica {
+    url: String,
+    pool: DbPool,
+    in_sync: AtomicBool,
+}
+
+/// Pools the resolution endpoints read from.
+///
+/// Reads go round robin to the replicas whose sync checkpoint is at most
+/// `max_lag` blocks behind the primary, and fall back to the primary when none
+/// is, or when the client asks for a fresh read.
+pub struct ReadPools {
+    primary: DbPool,
+    replicas: Vec<Replica>,
+    max_lag: i64,
+    next: AtomicUsize,
+    indexed_height: AtomicI64,
+}
+
+impl ReadPools {
+    pub fn new(primary: DbPool, replicas: Vec<(String, DbPool)>, max_lag: u64) -> Self {
+        ReadPools {
+            primary,
+            replicas: replicas
+                .into_iter()
+                .map(|(url, pool)| Replica {
+                    url: redact_url(&url),
+                    pool,
+                    in_sync: AtomicBool::new(false),
+                })
+                .collect(),
+            max_lag: max_lag as i64,
+            next: AtomicUsize::new(0),
+            indexed_height: AtomicI64::new(-1),
+        }
+    }
+
+    pub fn primary(&self) -> &DbPool {
+        &self.primary
+    }
+
+    /// Height of the last block indexed into the primary, as of the last check.
+    pub fn indexed_height(&self) -> Option<i64> {
+        Some(self.indexed_height.load(Ordering::Relaxed)).filter(|height| *height >= 0)
+    }
+
+    /// Whether `pool`, as returned by [`ReadPools::read`], is the primary.
+    /// Only its answers are up to date enough to be cached.
+    pub fn is_primary(&self, pool: &DbPool) -> bool {
+        std::ptr::eq(pool, &self.primary)
+    }
+
+    pub fn read(&self, fresh: bool) -> &DbPool {
+        if fresh || self.replicas.is_empty() {
+            return &self.primary;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.replicas.len())
+            .map(|i| &self.replicas[(start + i) % self.replicas.len()])
+            .find(|replica| replica.in_sync.load(Ordering::Relaxed))
+            .map_or(&self.primary, |replica| &replica.pool)
+    }
+
+    /// Read the sync checkpoint of the primary and compare every replica's to it.
+    pub async fn check_replicas(&self) -> Result<(), AppError> {
+        let primary_height = interact(&self.primary, query_sync_checkpoint).await?;
+        self.indexed_height
+            .store(primary_height.unwrap_or(-1), Ordering::Relaxed);
+        let primary_height = primary_height.unwrap_or(0);
+        for replica in &self.replicas {
+            let in_sync = match interact(&replica.pool, query_sync_checkpoint).await {
+                Ok(Some(height)) => primary_height - height <= self.max_lag,
+                Ok(None) => false,
+                Err(app_err) => {
+                    warn!("replica {} check failed: {app_err}", replica.url);
+                    false
+                }
+            };
+            if replica.in_sync.swap(in_sync, Ordering::Relaxed) != in_sync {
+                info!(
+                    "replica {} {}",
+                    replica.url,
+                    if in_sync { "in sync" } else { "stale" }
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn watch(&self, token: CancellationToken) {
+        loop {
+            if let Err(app_err) = self.check_replicas().await {
+                error!("replica check failed: {app_err}");
+            }
+            tokio::select! {
+                _ = token.cancelled() => return,
+                _ = time::sleep(CHECK_INTERVAL) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{establish_connection, run_migrations, update_sync_checkpoint};
+
+    async fn pool(url: String, height: Option<i64>) -> DbPool {
+        let pool = establish_connection(url, 1, Duration::from_secs(5)).unwrap();
+        interact(&pool, move |conn| {
+            run_migrations(conn)?;
+            match height {
+                Some(height) => update_sync_checkpoint(conn, height),
+                None => Ok(()),
+            }
+        })
+        .await
+        .unwrap();
+        pool
+    }
+
+    async fn read_height(pools: &ReadPools, fresh: bool) -> Option<i64> {
+        interact(pools.read(fresh), query_sync_checkpoint)
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn reads_go_to_replicas_in_sync() {
+        let dir = std::env::temp_dir().join(format!("replicas-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let url = |name: &str| format!("sqlite://{}", dir.join(name).display());
+        let primary = pool("sqlite://:memory:".into(), Some(110)).await;
+        let in_sync = pool(url("in-sync.db"), Some(106)).await;
+        let lagging = pool(url("lagging.db"), Some(100)).await;
+        let replicas = vec![
+            ("in-sync".to_string(), in_sync.clone()),
+            ("lagging".to_string(), lagging),
+        ];
+        let pools = ReadPools::new(primary, replicas, 5);
+
+        // Nothing checked yet: no replica counts as in sync.
+        assert_eq!(read_height(&pools, false).await, Some(110));
+        pools.check_replicas().await.unwrap();
+        assert_eq!(pools.indexed_height(), Some(110));
+        for _ in 0..4 {
+            assert_eq!(read_height(&pools, false).await, Some(106));
+        }
+        assert_eq!(read_height(&pools, true).await, Some(110));
+        assert!(pools.is_primary(pools.read(true)));
+        assert!(!pools.is_primary(pools.read(false)));
+
+        // Once both fall behind, reads go to the primary.
+        interact(&in_sync, |conn| update_sync_checkpoint(conn, 104))
+            .await
+            .unwrap();
+        pools.check_replicas().await.unwrap();
+        assert_eq!(read_height(&pools, false).await, Some(110));
+        assert!(pools.is_primary(pools.read(false)));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
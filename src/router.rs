@@ -1,42 +1,919 @@
 use crate::{
-    db::{DbPool, query_valid_did_doc, resolve_valid_handle},
+    cache::ResolutionCache,
+    config::AppConfig,
+    config::ValidationProfile,
+    db::{
+        delete_webhook, interact, query_deliveries, query_did_page, query_did_status,
+        query_handle_owner, query_history, query_invalid_cells, query_valid_did_doc,
+        query_valid_did_doc_cbor, query_valid_records_in, query_webhooks, resolve_valid_handle,
+        resolve_valid_local_id, retry_delivery, schema_version, search_handles, upsert_webhook,
+    },
     error::AppError,
-    util::check_did_str,
+    events::{EventBus, EventCursor},
+    mempool::PendingHandles,
+    rate_limit::RateLimiter,
+    replica::ReadPools,
+    types::{
+        DeliveryQuery, DidCursor, DidListQuery, DidPage, EventsQuery, HandleAvailability,
+        HandleSearchQuery, HistoryFilter, HistoryQuery, InvalidQuery, ReadQuery, ResolveRequest,
+        ResolveResponse, ResolveResult, ResolvedDocument, SubscribeQuery, VersionInfo,
+        WebhookRequest,
+    },
+    util::{check_did_str, check_handle_str, normalize_handle},
 };
 use actix_web::{
-    HttpResponse,
-    web::{Data, Path, block},
+    HttpRequest, HttpResponse,
+    http::header::{
+        AUTHORIZATION, CONTENT_ENCODING, CacheControl, CacheDirective, ETag, EntityTag, Header,
+        HttpDate, IF_NONE_MATCH, IfModifiedSince, IfNoneMatch, LastModified,
+    },
+    rt,
+    web::{Bytes, Data, Json, Path, Payload, Query},
 };
+use actix_ws::{CloseCode, CloseReason, Message};
+use futures_util::stream;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{select, time};
 
-pub async fn query_did_doc(path: Path<String>, pool: Data<DbPool>) -> HttpResponse {
+/// Blocks on top of a record's block after which its document is served with
+/// a long `max-age`; shallower records have to be revalidated on every use.
+const CONFIRMED_DEPTH: i64 = 24;
+const CONFIRMED_MAX_AGE: u32 = 300;
+
+/// Interval of the pings that detect dead `/subscribe` connections.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Interval of the comments that keep idle `/events` streams open through
+/// proxies.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Shortest handle prefix `/search/handles` accepts.
+const MIN_SEARCH_LEN: usize = 2;
+
+/// Answer a document request, or `304 Not Modified` when the client's copy
+/// matches by `If-None-Match` or, without it, `If-Modified-Since`.
+fn document_response(
+    req: &HttpRequest,
+    resolved: &ResolvedDocument,
+    indexed_height: Option<i64>,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(match &resolved.document_cid {
+        Some(cid) => cid.clone(),
+        None => format!("{}-{}", resolved.tx_hash, resolved.out_index),
+    });
+    // HTTP dates have a resolution of one second.
+    let last_modified = UNIX_EPOCH + Duration::from_secs(resolved.block_timestamp as u64 / 1000);
+    let cache_control = match indexed_height {
+        Some(height) if height - resolved.height >= CONFIRMED_DEPTH => vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(CONFIRMED_MAX_AGE),
+        ],
+        _ => vec![CacheDirective::Public, CacheDirective::NoCache],
+    };
+
+    let not_modified = if req.headers().contains_key(IF_NONE_MATCH) {
+        match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        }
+    } else {
+        match IfModifiedSince::parse(req) {
+            Ok(IfModifiedSince(since)) => last_modified <= SystemTime::from(since),
+            Err(_) => false,
+        }
+    };
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(ETag(etag))
+        .insert_header(LastModified(HttpDate::from(last_modified)))
+        .insert_header(CacheControl(cache_control));
+    if not_modified {
+        response.finish()
+    } else {
+        response.json(&resolved.document)
+    }
+}
+
+pub async fn query_did_doc(
+    req: HttpRequest,
+    path: Path<String>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+    cache: Data<ResolutionCache>,
+) -> HttpResponse {
     let did = path.into_inner();
-    let mut conn = pool.get().unwrap();
     if !check_did_str(&did) {
         return HttpResponse::from_error(AppError::IncompatibleDid(did));
     }
-    match block(move || query_valid_did_doc(&mut conn, did))
-        .await
-        .map_err(|e| AppError::RunTimeError(e.to_string()))
+    if !read.fresh
+        && let Some(resolved) = cache.get_doc(&did)
     {
-        Ok(res) => match res {
-            Ok(doc) => HttpResponse::Ok().json(doc),
-            Err(err) => HttpResponse::from_error(err),
-        },
+        return document_response(&req, &resolved, pools.indexed_height());
+    }
+    let generation = cache.generation();
+    let key = did.clone();
+    let pool = pools.read(read.fresh);
+    match interact(pool, move |conn| query_valid_did_doc(conn, did)).await {
+        Ok(resolved) => {
+            let response = document_response(&req, &resolved, pools.indexed_height());
+            if pools.is_primary(pool) {
+                cache.put_doc(key, resolved, generation);
+            }
+            response
+        }
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn query_did_doc_raw(
+    path: Path<String>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+) -> HttpResponse {
+    let did = path.into_inner();
+    if !check_did_str(&did) {
+        return HttpResponse::from_error(AppError::IncompatibleDid(did));
+    }
+    match interact(pools.read(read.fresh), move |conn| {
+        query_valid_did_doc_cbor(conn, did)
+    })
+    .await
+    {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type("application/cbor")
+            .body(bytes),
         Err(err) => HttpResponse::from_error(err),
     }
 }
 
-pub async fn resolve_handle(path: Path<String>, pool: Data<DbPool>) -> HttpResponse {
+pub async fn resolve_handle(
+    path: Path<String>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+    cache: Data<ResolutionCache>,
+) -> HttpResponse {
     let handle = path.into_inner();
-    let mut conn = pool.get().unwrap();
-    match block(move || resolve_valid_handle(&mut conn, handle))
-        .await
-        .map_err(|e| AppError::RunTimeError(e.to_string()))
+    if !read.fresh
+        && let Some(did) = cache.get_handle(&handle)
+    {
+        return HttpResponse::Ok().body(did);
+    }
+    let generation = cache.generation();
+    let key = handle.clone();
+    let pool = pools.read(read.fresh);
+    match interact(pool, move |conn| resolve_valid_handle(conn, handle)).await {
+        Ok(did) => {
+            if pools.is_primary(pool) {
+                cache.put_handle(key, did.clone(), generation);
+            }
+            HttpResponse::Ok().body(did)
+        }
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+/// Resolve many dids and handles at once. Every item gets its own result,
+/// failing items carry the error they would get from `/{did}`.
+pub async fn resolve_bulk(
+    body: Json<ResolveRequest>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+    config: Data<AppConfig>,
+) -> HttpResponse {
+    let ResolveRequest { dids, handles } = body.into_inner();
+    let max_batch = config.resolve_max_batch as usize;
+    if dids.len() + handles.len() > max_batch {
+        return HttpResponse::from_error(AppError::BatchTooLarge(max_batch));
+    }
+    let mut response = ResolveResponse::default();
+    let dids: Vec<String> = dids
+        .into_iter()
+        .filter(|did| {
+            let compatible = check_did_str(did);
+            if !compatible {
+                let err = AppError::IncompatibleDid(did.clone());
+                response.dids.insert(did.clone(), err.into());
+            }
+            compatible
+        })
+        .collect();
+    if dids.is_empty() && handles.is_empty() {
+        return HttpResponse::Ok().json(response);
+    }
+
+    // Handles are looked up normalized and answered under the requested spelling.
+    let query_handles: Vec<String> = handles.iter().map(|h| normalize_handle(h)).collect();
+    let query_dids = dids.clone();
+    let records = match interact(pools.read(read.fresh), move |conn| {
+        query_valid_records_in(conn, query_dids, query_handles)
+    })
+    .await
+    {
+        Ok(records) => records,
+        Err(err) => return HttpResponse::from_error(err),
+    };
+    let mut documents = HashMap::with_capacity(records.len());
+    let mut handle_dids = HashMap::with_capacity(records.len());
+    for (did, handle, document) in records {
+        if let Some(handle) = handle {
+            handle_dids.insert(normalize_handle(&handle), did.clone());
+        }
+        documents.insert(did, document);
+    }
+    for did in dids {
+        let result = match documents.get(&did) {
+            Some(document) => match serde_json::from_value(document.clone()) {
+                Ok(document) => ResolveResult::Document { document },
+                Err(_) => AppError::DidDocNoData(did.clone()).into(),
+            },
+            None => AppError::DidDocNotFound(did.clone()).into(),
+        };
+        response.dids.insert(did, result);
+    }
+    for handle in handles {
+        let result = match handle_dids.get(&normalize_handle(&handle)) {
+            Some(did) => ResolveResult::Did { did: did.clone() },
+            None => AppError::HandleNotFound(handle.clone()).into(),
+        };
+        response.handles.insert(handle, result);
+    }
+    HttpResponse::Ok().json(response)
+}
+
+pub async fn resolve_local_id(
+    path: Path<String>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+) -> HttpResponse {
+    let local_id = path.into_inner();
+    match interact(pools.read(read.fresh), move |conn| {
+        resolve_valid_local_id(conn, local_id)
+    })
+    .await
     {
-        Ok(res) => match res {
-            Ok(did) => HttpResponse::Ok().body(did),
-            Err(err) => HttpResponse::from_error(err),
+        Ok(dids) => HttpResponse::Ok().json(dids),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn query_status(
+    path: Path<String>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+) -> HttpResponse {
+    let did = path.into_inner();
+    if !check_did_str(&did) {
+        return HttpResponse::from_error(AppError::IncompatibleDid(did));
+    }
+    match interact(pools.read(read.fresh), move |conn| {
+        query_did_status(conn, did)
+    })
+    .await
+    {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn list_dids(
+    query: Query<DidListQuery>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+) -> HttpResponse {
+    let mut query = query.into_inner();
+    // Handles are matched the way they are compared, ignoring case.
+    query.handle_prefix = query.handle_prefix.as_deref().map(normalize_handle);
+    let after = match query.cursor.as_deref().map(str::parse::<DidCursor>) {
+        Some(Ok(cursor)) => Some(cursor),
+        Some(Err(err)) => return HttpResponse::from_error(err),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    // One extra row tells whether there is a next page.
+    match interact(pools.read(read.fresh), move |conn| {
+        query_did_page(conn, query, after, limit + 1)
+    })
+    .await
+    {
+        Ok(mut dids) => {
+            let cursor = if dids.len() as i64 > limit {
+                dids.truncate(limit as usize);
+                dids.last().map(|last| {
+                    DidCursor {
+                        height: last.height,
+                        tx_hash: last.tx_hash.clone(),
+                        out_index: last.out_index,
+                    }
+                    .to_string()
+                })
+            } else {
+                None
+            };
+            HttpResponse::Ok().json(DidPage { dids, cursor })
+        }
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn query_handle_search(
+    req: HttpRequest,
+    query: Query<HandleSearchQuery>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+    limiter: Data<RateLimiter>,
+) -> HttpResponse {
+    if let Some(client) = limiter.client(&req)
+        && !limiter.check(client)
+    {
+        return HttpResponse::from_error(AppError::RateLimited);
+    }
+    let prefix = normalize_handle(&query.q);
+    if prefix.chars().count() < MIN_SEARCH_LEN {
+        return HttpResponse::from_error(AppError::QueryTooShort(MIN_SEARCH_LEN));
+    }
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    match interact(pools.read(read.fresh), move |conn| {
+        search_handles(conn, prefix, limit)
+    })
+    .await
+    {
+        Ok(handles) => HttpResponse::Ok().json(handles),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+/// Whether a handle can still be claimed: it has to be well formed under the
+/// validation profile, not held by a live record and not claimed by a
+/// transaction in the node's pool.
+pub async fn query_handle_available(
+    path: Path<String>,
+    pools: Data<ReadPools>,
+    config: Data<AppConfig>,
+    pending: Data<PendingHandles>,
+) -> HttpResponse {
+    let handle = normalize_handle(&path);
+    let mut availability = HandleAvailability {
+        handle: handle.clone(),
+        available: false,
+        reason: None,
+        did: None,
+        tx_hash: None,
+    };
+    let well_formed = match config.validation_profile {
+        ValidationProfile::Generic => !handle.is_empty(),
+        _ => check_handle_str(&handle),
+    };
+    if !well_formed {
+        availability.reason = Some("invalid".to_string());
+        return HttpResponse::Ok().json(availability);
+    }
+    // The primary, a lagging replica could offer a handle that was just taken.
+    let query_handle = handle.clone();
+    match interact(pools.primary(), move |conn| {
+        query_handle_owner(conn, query_handle)
+    })
+    .await
+    {
+        Ok(Some(did)) => {
+            availability.reason = Some("registered".to_string());
+            availability.did = Some(did);
+        }
+        Ok(None) => match pending.get(&handle) {
+            Some(claim) => {
+                availability.reason = Some("pending".to_string());
+                availability.did = Some(claim.did);
+                availability.tx_hash = Some(claim.tx_hash);
+            }
+            None => availability.available = true,
         },
+        Err(err) => return HttpResponse::from_error(err),
+    }
+    HttpResponse::Ok().json(availability)
+}
+
+/// Every did that held a handle, matched ignoring case and a leading `@`.
+pub async fn query_handle_history(
+    path: Path<String>,
+    query: Query<HistoryQuery>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+) -> HttpResponse {
+    let handle = normalize_handle(&path);
+    history_response(HistoryFilter::Handle(handle), &query, &read, &pools).await
+}
+
+/// Every did an address owned.
+pub async fn query_address_history(
+    path: Path<String>,
+    query: Query<HistoryQuery>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+) -> HttpResponse {
+    let address = path.into_inner();
+    history_response(HistoryFilter::Address(address), &query, &read, &pools).await
+}
+
+async fn history_response(
+    filter: HistoryFilter,
+    query: &HistoryQuery,
+    read: &ReadQuery,
+    pools: &ReadPools,
+) -> HttpResponse {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    match interact(pools.read(read.fresh), move |conn| {
+        query_history(conn, filter, limit)
+    })
+    .await
+    {
+        Ok(history) => HttpResponse::Ok().json(history),
         Err(err) => HttpResponse::from_error(err),
     }
 }
+
+/// Stream identity events over a WebSocket as JSON text frames, starting
+/// after `?cursor=` or with the next event.
+pub async fn subscribe(
+    req: HttpRequest,
+    body: Payload,
+    query: Query<SubscribeQuery>,
+    pools: Data<ReadPools>,
+    bus: Data<EventBus>,
+) -> HttpResponse {
+    let mut events = match EventCursor::new(pools.primary().clone(), &bus, query.cursor).await {
+        Ok(events) => events,
+        Err(err) => return HttpResponse::from_error(err),
+    };
+    let (response, mut session, mut messages) = match actix_ws::handle(&req, body) {
+        Ok(handshake) => handshake,
+        Err(err) => return err.error_response(),
+    };
+    rt::spawn(async move {
+        let mut ping = time::interval(PING_INTERVAL);
+        let reason = loop {
+            select! {
+                event = events.next() => match event {
+                    Ok(event) => {
+                        let frame = serde_json::to_string(&event).unwrap_or_default();
+                        if session.text(frame).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(app_err) => {
+                        error!("event stream failed: {app_err}");
+                        break Some(CloseReason {
+                            code: CloseCode::Error,
+                            description: Some(app_err.to_string()),
+                        });
+                    }
+                },
+                message = messages.recv() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break None,
+                    Some(Ok(_)) => {}
+                },
+                _ = ping.tick() => {
+                    if session.ping(b"").await.is_err() {
+                        return;
+                    }
+                }
+            }
+        };
+        let _ = session.close(reason).await;
+    });
+    response
+}
+
+/// Stream identity events as Server-Sent Events, starting after `?since=` or
+/// the `Last-Event-ID` of a reconnecting client.
+pub async fn query_events(
+    req: HttpRequest,
+    query: Query<EventsQuery>,
+    pools: Data<ReadPools>,
+    bus: Data<EventBus>,
+) -> HttpResponse {
+    let query = query.into_inner();
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok()?.trim().parse::<i64>().ok());
+    let since = last_event_id.or(query.since);
+    let events = match EventCursor::new(pools.primary().clone(), &bus, since).await {
+        Ok(events) => events,
+        Err(err) => return HttpResponse::from_error(err),
+    };
+    let frames = stream::unfold((events, query), |(mut events, query)| async move {
+        let frame = loop {
+            select! {
+                event = events.next() => match event {
+                    Ok(event) if query.matches(&event) => {
+                        let data = serde_json::to_string(&event).unwrap_or_default();
+                        break format!("id: {}\ndata: {data}\n\n", event.seq);
+                    }
+                    Ok(_) => {}
+                    Err(app_err) => {
+                        error!("event stream failed: {app_err}");
+                        return None;
+                    }
+                },
+                _ = time::sleep(KEEPALIVE_INTERVAL) => break ":\n\n".to_string(),
+            }
+        };
+        Some((
+            Ok::<_, actix_web::Error>(Bytes::from(frame)),
+            (events, query),
+        ))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        // Keeps the compression middleware and nginx from buffering the stream.
+        .insert_header((CONTENT_ENCODING, "identity"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(frames)
+}
+
+pub async fn query_invalid(
+    query: Query<InvalidQuery>,
+    read: Query<ReadQuery>,
+    pools: Data<ReadPools>,
+) -> HttpResponse {
+    let since = query.since.unwrap_or(0);
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    match interact(pools.read(read.fresh), move |conn| {
+        query_invalid_cells(conn, since, limit)
+    })
+    .await
+    {
+        Ok(cells) => HttpResponse::Ok().json(cells),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn query_version(pools: Data<ReadPools>) -> HttpResponse {
+    match interact(pools.primary(), schema_version).await {
+        Ok(schema_version) => HttpResponse::Ok().json(VersionInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version,
+        }),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn query_metrics(cache: Data<ResolutionCache>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(cache.metrics())
+}
+
+/// Admit requests bearing `ADMIN_TOKEN`; without one configured the admin API
+/// is closed.
+fn check_admin(req: &HttpRequest, config: &AppConfig) -> Result<(), AppError> {
+    let Some(token) = &config.admin_token else {
+        return Err(AppError::Unauthorized);
+    };
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(AppError::Unauthorized)?;
+    // Comparing digests keeps the time taken independent of the token.
+    if Sha256::digest(bearer.as_bytes()) != Sha256::digest(token.0.as_bytes()) {
+        return Err(AppError::Unauthorized);
+    }
+    Ok(())
+}
+
+pub async fn list_webhooks(
+    req: HttpRequest,
+    pools: Data<ReadPools>,
+    config: Data<AppConfig>,
+) -> HttpResponse {
+    if let Err(err) = check_admin(&req, &config) {
+        return HttpResponse::from_error(err);
+    }
+    match interact(pools.primary(), query_webhooks).await {
+        Ok(webhooks) => HttpResponse::Ok().json(webhooks),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn create_webhook(
+    req: HttpRequest,
+    body: Json<WebhookRequest>,
+    pools: Data<ReadPools>,
+    config: Data<AppConfig>,
+) -> HttpResponse {
+    if let Err(err) = check_admin(&req, &config) {
+        return HttpResponse::from_error(err);
+    }
+    let WebhookRequest { url, secret } = body.into_inner();
+    match url::Url::parse(&url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+        _ => return HttpResponse::from_error(AppError::InvalidWebhook(url)),
+    }
+    if secret.is_empty() {
+        return HttpResponse::from_error(AppError::InvalidWebhook("empty secret".to_string()));
+    }
+    match interact(pools.primary(), move |conn| {
+        upsert_webhook(conn, url, secret)
+    })
+    .await
+    {
+        Ok(webhook) => HttpResponse::Ok().json(webhook),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn remove_webhook(
+    req: HttpRequest,
+    path: Path<i32>,
+    pools: Data<ReadPools>,
+    config: Data<AppConfig>,
+) -> HttpResponse {
+    if let Err(err) = check_admin(&req, &config) {
+        return HttpResponse::from_error(err);
+    }
+    let id = path.into_inner();
+    match interact(pools.primary(), move |conn| delete_webhook(conn, id)).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn list_deliveries(
+    req: HttpRequest,
+    path: Path<i32>,
+    query: Query<DeliveryQuery>,
+    pools: Data<ReadPools>,
+    config: Data<AppConfig>,
+) -> HttpResponse {
+    if let Err(err) = check_admin(&req, &config) {
+        return HttpResponse::from_error(err);
+    }
+    let webhook_id = path.into_inner();
+    let DeliveryQuery {
+        status,
+        before,
+        limit,
+    } = query.into_inner();
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    match interact(pools.primary(), move |conn| {
+        query_deliveries(conn, webhook_id, status, before, limit)
+    })
+    .await
+    {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+pub async fn retry_webhook_delivery(
+    req: HttpRequest,
+    path: Path<i64>,
+    pools: Data<ReadPools>,
+    config: Data<AppConfig>,
+) -> HttpResponse {
+    if let Err(err) = check_admin(&req, &config) {
+        return HttpResponse::from_error(err);
+    }
+    let id = path.into_inner();
+    match interact(pools.primary(), move |conn| retry_delivery(conn, id)).await {
+        Ok(delivery) => HttpResponse::Ok().json(delivery),
+        Err(err) => HttpResponse::from_error(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{establish_connection, insert_record, run_migrations},
+        mempool::PendingClaim,
+        models::NewDidRecord,
+    };
+    use actix_web::{
+        App,
+        http::StatusCode,
+        test::{TestRequest, call_and_read_body_json, init_service},
+        web,
+    };
+    use chrono::DateTime;
+
+    const CID: &str = "bafyreib2rxk3rybk3aobmv5cjuql3bm2twh4jo5ufhyu5q7nx3l6aqdbeq";
+    /// 2023-11-14T22:13:20Z.
+    const BLOCK_TIMESTAMP: i64 = 1_700_000_000_000;
+
+    fn resolved(document_cid: Option<&str>) -> ResolvedDocument {
+        ResolvedDocument {
+            document: serde_json::from_value(serde_json::json!({
+                "verificationMethods": {},
+                "alsoKnownAs": [],
+                "services": {},
+            }))
+            .unwrap(),
+            tx_hash: "ab".repeat(32),
+            out_index: 1,
+            document_cid: document_cid.map(str::to_string),
+            height: 100,
+            block_timestamp: BLOCK_TIMESTAMP,
+        }
+    }
+
+    fn respond(headers: &[(&str, &str)], indexed_height: Option<i64>) -> HttpResponse {
+        let mut req = TestRequest::default();
+        for header in headers {
+            req = req.insert_header(*header);
+        }
+        document_response(&req.to_http_request(), &resolved(Some(CID)), indexed_height)
+    }
+
+    fn header<'a>(res: &'a HttpResponse, name: &str) -> &'a str {
+        res.headers().get(name).unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn unconditional_request_gets_the_document() {
+        let res = respond(&[], Some(110));
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, "etag"), format!("\"{CID}\""));
+        assert_eq!(
+            header(&res, "last-modified"),
+            "Tue, 14 Nov 2023 22:13:20 GMT"
+        );
+        assert_eq!(header(&res, "cache-control"), "public, no-cache");
+
+        let res = respond(&[], Some(100 + CONFIRMED_DEPTH));
+        assert_eq!(header(&res, "cache-control"), "public, max-age=300");
+        let res = respond(&[], None);
+        assert_eq!(header(&res, "cache-control"), "public, no-cache");
+
+        let req = TestRequest::default().to_http_request();
+        let res = document_response(&req, &resolved(None), None);
+        assert_eq!(header(&res, "etag"), format!("\"{}-1\"", "ab".repeat(32)));
+    }
+
+    #[test]
+    fn if_none_match_is_compared_weakly() {
+        let etag = format!("\"{CID}\"");
+        let weak = format!("W/\"{CID}\"");
+        let listed = format!("\"other\", {etag}");
+        for matching in [etag.as_str(), weak.as_str(), listed.as_str(), "*"] {
+            let res = respond(&[("if-none-match", matching)], None);
+            assert_eq!(res.status(), StatusCode::NOT_MODIFIED, "{matching}");
+            assert_eq!(header(&res, "etag"), etag);
+        }
+        let res = respond(&[("if-none-match", "\"other\"")], None);
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn if_modified_since_compares_to_the_block_time() {
+        let res = respond(
+            &[("if-modified-since", "Tue, 14 Nov 2023 22:13:20 GMT")],
+            None,
+        );
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let res = respond(
+            &[("if-modified-since", "Wed, 15 Nov 2023 00:00:00 GMT")],
+            None,
+        );
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let res = respond(
+            &[("if-modified-since", "Tue, 14 Nov 2023 22:13:19 GMT")],
+            None,
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = respond(&[("if-modified-since", "yesterday")], None);
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence() {
+        let res = respond(
+            &[
+                ("if-none-match", "\"other\""),
+                ("if-modified-since", "Wed, 15 Nov 2023 00:00:00 GMT"),
+            ],
+            None,
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    fn config(validation_profile: ValidationProfile) -> AppConfig {
+        AppConfig {
+            data_base_url: "sqlite://:memory:".to_string(),
+            data_base_read_urls: Vec::new(),
+            replica_max_lag: 10,
+            db_pool_size: 1,
+            db_pool_timeout: 5,
+            cache_size: 0,
+            cache_ttl: 60,
+            resolve_max_batch: 100,
+            search_rate_limit: 0,
+            trusted_proxy: false,
+            webhooks: Vec::new(),
+            webhook_secret: None,
+            webhook_max_attempts: 10,
+            admin_token: None,
+            mempool_poll_interval: 0,
+            ckb_node: String::new(),
+            ckb_network: "ckb_testnet".to_string(),
+            listen_port: 9533,
+            log_level: "info".to_string(),
+            worker_num: 1,
+            start_height: 0,
+            deployments: Vec::new(),
+            validation_profile,
+        }
+    }
+
+    #[actix_web::test]
+    async fn handle_availability() {
+        // A single pooled connection keeps the in-memory database alive.
+        let pool =
+            establish_connection("sqlite://:memory:".into(), 1, Duration::from_secs(5)).unwrap();
+        interact(&pool, |conn| {
+            run_migrations(conn)?;
+            insert_record(
+                conn,
+                &NewDidRecord {
+                    did: "did:web5:a".to_string(),
+                    handle: Some("Alice.example.com".to_string()),
+                    tx_hash: "aa".repeat(32),
+                    document: serde_json::json!({}),
+                    valid: true,
+                    service_errors: serde_json::json!({}),
+                    created_at: DateTime::UNIX_EPOCH,
+                    ..Default::default()
+                },
+            )
+        })
+        .await
+        .unwrap();
+        let pending = PendingHandles::default();
+        pending.replace(HashMap::from([(
+            "bob.example.com".to_string(),
+            PendingClaim {
+                did: "did:web5:b".to_string(),
+                tx_hash: "bb".repeat(32),
+            },
+        )]));
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ReadPools::new(pool, Vec::new(), 10)))
+                .app_data(Data::new(config(ValidationProfile::Atproto)))
+                .app_data(Data::new(pending))
+                .route(
+                    "/handle/{handle}/available",
+                    web::get().to(query_handle_available),
+                ),
+        )
+        .await;
+        let check = async |handle: &str| -> serde_json::Value {
+            let req = TestRequest::get()
+                .uri(&format!("/handle/{handle}/available"))
+                .to_request();
+            call_and_read_body_json(&app, req).await
+        };
+
+        assert_eq!(
+            check("@ALICE.example.com").await,
+            serde_json::json!({
+                "handle": "alice.example.com",
+                "available": false,
+                "reason": "registered",
+                "did": "did:web5:a",
+            })
+        );
+        assert_eq!(
+            check("bob.example.com").await,
+            serde_json::json!({
+                "handle": "bob.example.com",
+                "available": false,
+                "reason": "pending",
+                "did": "did:web5:b",
+                "txHash": "bb".repeat(32),
+            })
+        );
+        assert_eq!(
+            check("carol.example.com").await,
+            serde_json::json!({"handle": "carol.example.com", "available": true})
+        );
+        assert_eq!(
+            check("no_dots").await,
+            serde_json::json!({"handle": "no_dots", "available": false, "reason": "invalid"})
+        );
+    }
+}
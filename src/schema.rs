@@ -1,37 +1,146 @@
 // @generated automatically by Diesel CLI.
+//
+// The `indexer.` qualifier is dropped so that the tables resolve on SQLite too
+// (postgres connections put `indexer` on the search path), and time columns use
+// `crate::sql_types::UtcDateTime`.
 
 pub mod indexer {
     diesel::table! {
-        indexer.did_delete_record (did) {
+        use diesel::sql_types::*;
+        use crate::sql_types::UtcDateTime;
+
+        did_cell (txHash, outIndex) {
+            txHash -> Varchar,
+            outIndex -> Int4,
+            did -> Varchar,
+            ckbAddress -> Varchar,
+            deployment -> Varchar,
+            cellData -> Bytea,
+            height -> Int8,
+            blockTimestamp -> Int8,
+            spentTxHash -> Nullable<Varchar>,
+            spentInIndex -> Nullable<Int4>,
+            spentHeight -> Nullable<Int8>,
+        }
+    }
+
+    diesel::table! {
+        use diesel::sql_types::*;
+        use crate::sql_types::UtcDateTime;
+
+        did_delete_record (txHash, inIndex) {
             did -> Varchar,
             ckbAddress -> Varchar,
-            handle -> Varchar,
-            signingKey -> Varchar,
+            handle -> Nullable<Varchar>,
+            signingKey -> Nullable<Varchar>,
             txHash -> Varchar,
             inIndex -> Int4,
-            document -> Varchar,
+            document -> Jsonb,
             height -> Int8,
-            deletedAt -> Varchar,
+            deletedAt -> UtcDateTime,
+            deployment -> Varchar,
+            blockTimestamp -> Int8,
         }
     }
 
     diesel::table! {
-        indexer.did_record (did) {
+        use diesel::sql_types::*;
+        use crate::sql_types::UtcDateTime;
+
+        did_event (seq) {
+            seq -> Int8,
+            did -> Varchar,
+            kind -> Varchar,
+            handle -> Nullable<Varchar>,
+            txHash -> Varchar,
+            index -> Int4,
+            height -> Int8,
+            createdAt -> UtcDateTime,
+            previousHandle -> Nullable<Varchar>,
+        }
+    }
+
+    diesel::table! {
+        use diesel::sql_types::*;
+        use crate::sql_types::UtcDateTime;
+
+        did_record (did) {
             did -> Varchar,
             ckbAddress -> Varchar,
-            handle -> Varchar,
-            signingKey -> Varchar,
+            handle -> Nullable<Varchar>,
+            signingKey -> Nullable<Varchar>,
             txHash -> Varchar,
             outIndex -> Int4,
-            document -> Varchar,
+            document -> Jsonb,
             height -> Int8,
-            createdAt -> Varchar,
+            createdAt -> UtcDateTime,
             valid -> Bool,
+            deployment -> Varchar,
+            localId -> Nullable<Varchar>,
+            cellData -> Nullable<Bytea>,
+            pdsEndpoint -> Nullable<Varchar>,
+            serviceErrors -> Jsonb,
+            documentCbor -> Nullable<Bytea>,
+            documentCid -> Nullable<Varchar>,
+            blockTimestamp -> Int8,
+        }
+    }
+
+    diesel::table! {
+        use diesel::sql_types::*;
+        use crate::sql_types::UtcDateTime;
+
+        invalid_cell (txHash, outIndex) {
+            txHash -> Varchar,
+            outIndex -> Int4,
+            did -> Varchar,
+            category -> Varchar,
+            reason -> Varchar,
+            height -> Int8,
+            createdAt -> UtcDateTime,
+        }
+    }
+
+    diesel::table! {
+        use diesel::sql_types::*;
+        use crate::sql_types::UtcDateTime;
+
+        sync_checkpoint (id) {
+            id -> Int4,
+            height -> Int8,
+            updatedAt -> UtcDateTime,
+        }
+    }
+
+    diesel::table! {
+        use diesel::sql_types::*;
+        use crate::sql_types::UtcDateTime;
+
+        webhook (id) {
+            id -> Int4,
+            url -> Varchar,
+            secret -> Varchar,
+            createdAt -> UtcDateTime,
+        }
+    }
+
+    diesel::table! {
+        use diesel::sql_types::*;
+        use crate::sql_types::UtcDateTime;
+
+        webhook_delivery (id) {
+            id -> Int8,
+            webhookId -> Int4,
+            eventSeq -> Int8,
+            payload -> Jsonb,
+            status -> Varchar,
+            attempts -> Int4,
+            nextAttemptAt -> UtcDateTime,
+            lastError -> Nullable<Varchar>,
+            createdAt -> UtcDateTime,
+            deliveredAt -> Nullable<UtcDateTime>,
         }
     }
 
-    diesel::allow_tables_to_appear_in_same_query!(
-        did_delete_record,
-        did_record,
-    );
+    diesel::allow_tables_to_appear_in_same_query!(did_cell, did_delete_record, did_record,);
 }
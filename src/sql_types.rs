@@ -0,0 +1,55 @@
+//! SQL types whose native representation differs between the postgres and the
+//! SQLite backend, so that `schema.rs` can describe both.
+
+use chrono::{DateTime, Utc};
+use diesel::{
+    deserialize::{self, FromSql},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    query_builder::QueryId,
+    serialize::{self, Output, ToSql},
+    sql_types::{self, SqlType},
+    sqlite::{Sqlite, SqliteValue},
+};
+
+/// Point in time with time zone: `TIMESTAMPTZ` on postgres, text on SQLite.
+#[derive(Debug, Clone, Copy, Default, QueryId, SqlType)]
+#[diesel(postgres_type(oid = 1184, array_oid = 1185))]
+#[diesel(sqlite_type(name = "Text"))]
+pub struct UtcDateTime;
+
+impl FromSql<UtcDateTime, Pg> for DateTime<Utc> {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        <Self as FromSql<sql_types::Timestamptz, Pg>>::from_sql(bytes)
+    }
+}
+
+impl FromSql<UtcDateTime, Sqlite> for DateTime<Utc> {
+    fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        <Self as FromSql<sql_types::TimestamptzSqlite, Sqlite>>::from_sql(value)
+    }
+}
+
+/// Bind value of a [`UtcDateTime`] column, for `#[diesel(serialize_as)]` on
+/// `DateTime<Utc>` fields and for filters.
+#[derive(Debug, Clone, Copy, AsExpression)]
+#[diesel(sql_type = UtcDateTime)]
+pub struct UtcTimestamp(pub DateTime<Utc>);
+
+impl From<DateTime<Utc>> for UtcTimestamp {
+    fn from(time: DateTime<Utc>) -> Self {
+        UtcTimestamp(time)
+    }
+}
+
+impl ToSql<UtcDateTime, Pg> for UtcTimestamp {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <DateTime<Utc> as ToSql<sql_types::Timestamptz, Pg>>::to_sql(&self.0, out)
+    }
+}
+
+impl ToSql<UtcDateTime, Sqlite> for UtcTimestamp {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        <DateTime<Utc> as ToSql<sql_types::TimestamptzSqlite, Sqlite>>::to_sql(&self.0, out)
+    }
+}
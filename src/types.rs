@@ -1,3 +1,8 @@
+use crate::{
+    error::AppError,
+    models::{DidDeleteRecord, DidEvent, DidRecord, DidSummary, InvalidCell},
+};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
@@ -6,6 +11,9 @@ pub struct Service {
     #[serde(rename = "type")]
     pub r#type: String,
     pub endpoint: String,
+    /// Fields outside the known schema, kept so the rendered service is complete.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -15,4 +23,343 @@ pub struct Web5DocumentData {
     #[serde(rename = "alsoKnownAs")]
     pub also_known_as: Vec<String>,
     pub services: BTreeMap<String, Service>,
+    /// Fields outside the known schema, kept so the rendered document is complete.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+/// A valid did document with the record fields HTTP caching is derived from.
+#[derive(Debug, Clone)]
+pub struct ResolvedDocument {
+    pub document: Web5DocumentData,
+    pub tx_hash: String,
+    pub out_index: i32,
+    pub document_cid: Option<String>,
+    pub height: i64,
+    pub block_timestamp: i64,
+}
+
+/// Body of `POST /resolve`.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ResolveRequest {
+    #[serde(default)]
+    pub dids: Vec<String>,
+    #[serde(default)]
+    pub handles: Vec<String>,
+}
+
+/// Result of one item of a bulk resolution.
+#[derive(Debug, Serialize, Clone)]
+#[serde(untagged)]
+pub enum ResolveResult {
+    Document { document: Web5DocumentData },
+    Did { did: String },
+    Error { error: String, message: String },
+}
+
+impl From<AppError> for ResolveResult {
+    fn from(app_err: AppError) -> Self {
+        ResolveResult::Error {
+            error: app_err.category().to_string(),
+            message: app_err.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ResolveResponse {
+    pub dids: BTreeMap<String, ResolveResult>,
+    pub handles: BTreeMap<String, ResolveResult>,
+}
+
+/// Columns a validation profile extracts from a did document.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DerivedFields {
+    pub handle: Option<String>,
+    pub signing_key: Option<String>,
+    pub pds_endpoint: Option<String>,
+    pub service_errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DidStatus {
+    pub did: String,
+    pub status: String,
+    pub record: Option<DidRecord>,
+    pub delete_record: Option<DidDeleteRecord>,
+    pub rejections: Vec<InvalidCell>,
+}
+
+/// Query string accepted by the resolution endpoints.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ReadQuery {
+    /// Read from the primary database instead of a replica.
+    #[serde(default)]
+    pub fresh: bool,
+}
+
+/// Query string of `/dids`.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DidListQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub handle_prefix: Option<String>,
+    pub from_height: Option<i64>,
+    pub to_height: Option<i64>,
+    /// Creation time range, both ends included.
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub address: Option<String>,
+    /// Key of `verificationMethods` the document must have, e.g. `atproto`.
+    pub verification_method: Option<String>,
+    /// `type` of any entry in `services`.
+    pub service_type: Option<String>,
+    /// Exact entry of `alsoKnownAs`.
+    pub also_known_as: Option<String>,
+}
+
+/// Position after a record in `(height, txHash, outIndex)` order, rendered as
+/// `<height>:<txHash>:<outIndex>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DidCursor {
+    pub height: i64,
+    pub tx_hash: String,
+    pub out_index: i32,
+}
+
+impl std::fmt::Display for DidCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.height, self.tx_hash, self.out_index)
+    }
+}
+
+impl std::str::FromStr for DidCursor {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppError::InvalidCursor(s.to_string());
+        let mut fields = s.split(':');
+        let (Some(height), Some(tx_hash), Some(out_index), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        Ok(DidCursor {
+            height: height.parse().map_err(|_| invalid())?,
+            tx_hash: tx_hash.to_string(),
+            out_index: out_index.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A page of `/dids`. `cursor` is absent on the last page.
+#[derive(Debug, Serialize, Clone)]
+pub struct DidPage {
+    pub dids: Vec<DidSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+}
+
+/// Query string of `/subscribe`.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SubscribeQuery {
+    /// Sequence number of the last event seen, replay starts after it.
+    pub cursor: Option<i64>,
+}
+
+/// Query string of `/events`.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct EventsQuery {
+    /// Sequence number of the last event seen, overridden by `Last-Event-ID`.
+    pub since: Option<i64>,
+    /// Only events of this did.
+    pub did: Option<String>,
+    /// Only events whose handle starts with this, ignoring case.
+    pub handle_prefix: Option<String>,
+}
+
+impl EventsQuery {
+    pub fn matches(&self, event: &DidEvent) -> bool {
+        self.did.as_ref().is_none_or(|did| *did == event.did)
+            && self.handle_prefix.as_ref().is_none_or(|prefix| {
+                event
+                    .handle
+                    .as_ref()
+                    .is_some_and(|handle| handle.to_lowercase().starts_with(&prefix.to_lowercase()))
+            })
+    }
+}
+
+/// Query string of `/search/handles`.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HandleSearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct HandleMatch {
+    pub handle: String,
+    pub did: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct InvalidQuery {
+    pub since: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Answer of `/handle/{handle}/available`.
+#[derive(Debug, Serialize, Clone)]
+pub struct HandleAvailability {
+    /// The normalized handle.
+    pub handle: String,
+    pub available: bool,
+    /// Why the handle can't be claimed: `invalid`, `registered` or `pending`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Did holding or claiming the handle.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub did: Option<String>,
+    /// Transaction in the node's pool claiming the handle.
+    #[serde(rename = "txHash", skip_serializing_if = "Option::is_none")]
+    pub tx_hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HistoryQuery {
+    pub limit: Option<i64>,
+}
+
+/// What `/handle/{handle}/history` and `/address/{addr}/history` trace.
+#[derive(Debug, Clone)]
+pub enum HistoryFilter {
+    /// Lowercase handle.
+    Handle(String),
+    Address(String),
+}
+
+/// Where a record of the history starts or ends: the output that carried it,
+/// or the input that removed it.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPoint {
+    pub tx_hash: String,
+    pub index: i32,
+    pub height: i64,
+    #[serde(serialize_with = "crate::util::serialize_time")]
+    pub time: DateTime<Utc>,
+}
+
+/// A record a did held. `removed` is null while the record is live, and
+/// `created` for removals indexed before did cells were stored.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub did: String,
+    pub handle: Option<String>,
+    pub ckb_address: String,
+    pub created: Option<HistoryPoint>,
+    pub removed: Option<HistoryPoint>,
+}
+
+impl HistoryEntry {
+    /// Height of the latest change, the order of the history.
+    pub fn height(&self) -> i64 {
+        self.removed
+            .as_ref()
+            .or(self.created.as_ref())
+            .map_or(0, |point| point.height)
+    }
+}
+
+/// Body of `POST /admin/webhooks`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebhookRequest {
+    pub url: String,
+    /// Key of the `X-Web5-Signature` HMAC.
+    pub secret: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DeliveryQuery {
+    /// `pending`, `delivered` or `failed`.
+    pub status: Option<String>,
+    /// Only deliveries with a smaller id, to page back in time.
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    pub version: String,
+    pub schema_version: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = DidCursor {
+            height: 18_000_000,
+            tx_hash: "ab".repeat(32),
+            out_index: 2,
+        };
+        assert_eq!(DidCursor::from_str(&cursor.to_string()).unwrap(), cursor);
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for cursor in ["", "1:ab", "1:ab:2:3", "x:ab:2", "1:ab:x", "-:ab:"] {
+            assert!(
+                matches!(DidCursor::from_str(cursor), Err(AppError::InvalidCursor(c)) if c == cursor),
+                "{cursor}"
+            );
+        }
+    }
+
+    fn event(did: &str, handle: Option<&str>) -> DidEvent {
+        DidEvent {
+            did: did.to_string(),
+            handle: handle.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn events_query_matches_did_and_handle_prefix() {
+        let alice = event("did:web5:a", Some("Alice.example.com"));
+        let anonymous = event("did:web5:b", None);
+        assert!(EventsQuery::default().matches(&alice));
+        assert!(EventsQuery::default().matches(&anonymous));
+
+        let by_did = EventsQuery {
+            did: Some("did:web5:a".to_string()),
+            ..Default::default()
+        };
+        assert!(by_did.matches(&alice));
+        assert!(!by_did.matches(&anonymous));
+
+        let by_prefix = EventsQuery {
+            handle_prefix: Some("aLI".to_string()),
+            ..Default::default()
+        };
+        assert!(by_prefix.matches(&alice));
+        assert!(!by_prefix.matches(&anonymous));
+        assert!(!by_prefix.matches(&event("did:web5:c", Some("bob.example.com"))));
+
+        let both = EventsQuery {
+            did: Some("did:web5:c".to_string()),
+            handle_prefix: Some("ali".to_string()),
+            ..Default::default()
+        };
+        assert!(!both.matches(&alice));
+    }
 }
@@ -0,0 +1,382 @@
+use crate::{
+    db::{DbPool, claim_due_deliveries, interact, query_webhooks, update_delivery},
+    error::AppError,
+    events::EventBus,
+    models::{Webhook, WebhookDelivery},
+};
+use chrono::{TimeDelta, Utc};
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{select, time};
+use tokio_util::sync::CancellationToken;
+
+/// Deliveries posted per round.
+const BATCH: i64 = 50;
+/// How often due retries are looked for when no new event wakes the worker.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the deliveries of a round are held back from other workers. The
+/// round posts them concurrently, so it is done in about one request timeout.
+const CLAIM_LEASE: TimeDelta = TimeDelta::seconds(60);
+const BACKOFF_BASE: i64 = 10;
+const BACKOFF_MAX: i64 = 3600;
+/// Longest response body kept as the error of a failed attempt.
+const ERROR_BODY_LEN: usize = 256;
+
+/// State of an outbox entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Not delivered yet, posted again once `nextAttemptAt` is reached.
+    Pending,
+    /// Answered with a 2xx status.
+    Delivered,
+    /// Gave up after the configured number of attempts.
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+/// `X-Web5-Signature` value for `body` sent at unix time `timestamp`: the hex
+/// HMAC-SHA256 of `"{timestamp}.{body}"` keyed with the webhook secret.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!(
+        "t={timestamp},v1={}",
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Wait before attempt `attempts + 1`: doubles from 10 seconds up to an hour.
+fn backoff(attempts: i32) -> TimeDelta {
+    let exp = (attempts - 1).clamp(0, 20) as u32;
+    TimeDelta::seconds((BACKOFF_BASE << exp).min(BACKOFF_MAX))
+}
+
+/// Posts the webhook outbox. Entries are written with the events they carry,
+/// so whatever was not delivered before a restart is picked up here again.
+pub struct WebhookWorker {
+    pool: DbPool,
+    client: reqwest::Client,
+    max_attempts: i32,
+}
+
+impl WebhookWorker {
+    pub fn new(pool: DbPool, max_attempts: u64) -> Self {
+        WebhookWorker {
+            pool,
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("build webhook http client"),
+            max_attempts: max_attempts.max(1) as i32,
+        }
+    }
+
+    pub async fn run(self, events: Arc<EventBus>, token: CancellationToken) {
+        let mut receiver = events.subscribe();
+        loop {
+            // A full batch means more may be due right away.
+            let full = match self.deliver_due().await {
+                Ok(count) => count as i64 == BATCH,
+                Err(app_err) => {
+                    error!("webhook delivery failed: {app_err}");
+                    false
+                }
+            };
+            if full {
+                continue;
+            }
+            select! {
+                _ = token.cancelled() => break,
+                // Lagging only means several events arrived, they are all in the outbox.
+                _ = receiver.recv() => {},
+                _ = time::sleep(POLL_INTERVAL) => {},
+            }
+        }
+    }
+
+    async fn deliver_due(&self) -> Result<usize, AppError> {
+        let (webhooks, due) = interact(&self.pool, |conn| {
+            let due = claim_due_deliveries(conn, BATCH, CLAIM_LEASE)?;
+            if due.is_empty() {
+                return Ok((HashMap::new(), due));
+            }
+            let webhooks: HashMap<i32, Webhook> = query_webhooks(conn)?
+                .into_iter()
+                .map(|webhook| (webhook.id, webhook))
+                .collect();
+            Ok((webhooks, due))
+        })
+        .await?;
+        let count = due.len();
+        let attempts = due.into_iter().filter_map(|delivery| {
+            // Removed since the batch was loaded, its deliveries went with it.
+            let webhook = webhooks.get(&delivery.webhook_id)?;
+            Some(self.attempt(webhook, delivery))
+        });
+        let delivered = join_all(attempts).await;
+        interact(&self.pool, move |conn| {
+            conn.transaction(|conn| {
+                for delivery in &delivered {
+                    update_delivery(conn, delivery)?;
+                }
+                Ok(())
+            })
+        })
+        .await?;
+        Ok(count)
+    }
+
+    /// Post one delivery and return it with the outcome recorded.
+    async fn attempt(&self, webhook: &Webhook, mut delivery: WebhookDelivery) -> WebhookDelivery {
+        let body = delivery.payload.to_string();
+        let kind = delivery.payload["kind"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let now = Utc::now();
+        let signature = sign(&webhook.secret, now.timestamp(), body.as_bytes());
+        let res = self
+            .client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Web5-Delivery", delivery.id.to_string())
+            .header("X-Web5-Event", kind)
+            .header("X-Web5-Signature", signature)
+            .body(body)
+            .send()
+            .await;
+        let error = match res {
+            Ok(res) if res.status().is_success() => None,
+            Ok(res) => {
+                let status = res.status();
+                let mut text = res.text().await.unwrap_or_default();
+                text.truncate(text.floor_char_boundary(ERROR_BODY_LEN));
+                Some(format!("{status}: {text}"))
+            }
+            Err(e) => Some(e.to_string()),
+        };
+        delivery.attempts += 1;
+        delivery.last_error = error;
+        match &delivery.last_error {
+            None => {
+                delivery.status = DeliveryStatus::Delivered.as_str().to_string();
+                delivery.delivered_at = Some(now);
+            }
+            Some(error) if delivery.attempts >= self.max_attempts => {
+                warn!(
+                    "webhook delivery {} to {} failed for good: {error}",
+                    delivery.id, webhook.url
+                );
+                delivery.status = DeliveryStatus::Failed.as_str().to_string();
+            }
+            Some(error) => {
+                debug!(
+                    "webhook delivery {} to {} failed: {error}",
+                    delivery.id, webhook.url
+                );
+                delivery.next_attempt_at = now + backoff(delivery.attempts);
+            }
+        }
+        delivery
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{
+            enqueue_deliveries, establish_connection, insert_did_event, tests::sqlite_memory,
+            upsert_webhook,
+        },
+        models::NewDidEvent,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    /// Request as received by [`stub`]: lowercased header names and the body.
+    struct Received {
+        headers: HashMap<String, String>,
+        body: String,
+    }
+
+    /// HTTP server on a free local port answering its connections with
+    /// `statuses` in turn and passing on what it received.
+    async fn stub(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = [0; 4096];
+                let (head_len, body_len) = loop {
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let head = String::from_utf8_lossy(&buf[..end]).to_lowercase();
+                        let len = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length:"))
+                            .map_or(0, |len| len.trim().parse().unwrap());
+                        break (end + 4, len);
+                    }
+                };
+                while buf.len() < head_len + body_len {
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+                let head = String::from_utf8_lossy(&buf[..head_len]).to_string();
+                let headers = head
+                    .lines()
+                    .skip(1)
+                    .filter_map(|line| line.split_once(':'))
+                    .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+                    .collect();
+                let body = String::from_utf8_lossy(&buf[head_len..]).to_string();
+                let reason = if status < 300 { "OK" } else { "Error" };
+                let response = format!(
+                    "HTTP/1.1 {status} {reason}\r\ncontent-length: 4\r\nconnection: close\r\n\r\nnope"
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                let _ = sender.send(Received { headers, body });
+            }
+        });
+        (url, receiver)
+    }
+
+    fn worker(max_attempts: u64) -> WebhookWorker {
+        // Attempts don't touch the database, the pool never connects.
+        let pool =
+            establish_connection("sqlite://:memory:".into(), 1, Duration::from_secs(1)).unwrap();
+        WebhookWorker::new(pool, max_attempts)
+    }
+
+    fn webhook(url: String) -> Webhook {
+        Webhook {
+            id: 1,
+            url,
+            secret: "topsecret".to_string(),
+            created_at: Utc::now(),
+        }
+    }
+
+    fn delivery() -> WebhookDelivery {
+        WebhookDelivery {
+            id: 7,
+            webhook_id: 1,
+            event_seq: 42,
+            payload: serde_json::json!({"seq": 42, "kind": "handle_change"}),
+            status: DeliveryStatus::Pending.as_str().to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn signature_is_hmac_of_timestamp_and_body() {
+        assert_eq!(
+            sign("topsecret", 1700000000, br#"{"seq":1}"#),
+            "t=1700000000,v1=81f1bc7e07c2ff76ccc34bbf866dde997ff1cfc92450c3b08d78368765d52c63"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_an_hour() {
+        let schedule: Vec<i64> = (1..=11).map(|n| backoff(n).num_seconds()).collect();
+        assert_eq!(
+            schedule,
+            [10, 20, 40, 80, 160, 320, 640, 1280, 2560, 3600, 3600]
+        );
+        assert_eq!(backoff(0).num_seconds(), 10);
+        assert_eq!(backoff(i32::MAX).num_seconds(), 3600);
+    }
+
+    #[actix_web::test]
+    async fn failed_attempt_is_retried() {
+        let (url, mut received) = stub(vec![500, 200]).await;
+        let (worker, webhook) = (worker(10), webhook(url));
+
+        let before = Utc::now();
+        let delivery = worker.attempt(&webhook, delivery()).await;
+        assert_eq!(delivery.status, "pending");
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(
+            delivery.last_error.as_deref(),
+            Some("500 Internal Server Error: nope")
+        );
+        assert!(delivery.next_attempt_at >= before + backoff(1));
+        assert!(delivery.next_attempt_at <= Utc::now() + backoff(1));
+
+        let delivery = worker.attempt(&webhook, delivery).await;
+        assert_eq!(delivery.status, "delivered");
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.last_error, None);
+        assert!(delivery.delivered_at.is_some());
+
+        for _ in 0..2 {
+            let request = received.recv().await.unwrap();
+            assert_eq!(request.headers["x-web5-delivery"], "7");
+            assert_eq!(request.headers["x-web5-event"], "handle_change");
+            assert_eq!(request.headers["content-type"], "application/json");
+            let signature = &request.headers["x-web5-signature"];
+            let timestamp: i64 = signature
+                .strip_prefix("t=")
+                .and_then(|rest| rest.split(',').next())
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(
+                *signature,
+                sign("topsecret", timestamp, request.body.as_bytes())
+            );
+            assert_eq!(request.body, delivery.payload.to_string());
+        }
+    }
+
+    #[test]
+    fn claimed_deliveries_are_held_back() {
+        let mut conn = sqlite_memory();
+        upsert_webhook(&mut conn, "https://hook.example.com".into(), "s".into()).unwrap();
+        let event = insert_did_event(&mut conn, &NewDidEvent::default()).unwrap();
+        enqueue_deliveries(&mut conn, &event).unwrap();
+        let mut claim = |lease| -> Vec<i64> {
+            let claimed = claim_due_deliveries(&mut conn, BATCH, lease).unwrap();
+            claimed.into_iter().map(|delivery| delivery.id).collect()
+        };
+
+        // A claimer gone before recording the outcome leaves it due again
+        // once the lease ran out.
+        assert_eq!(claim(TimeDelta::zero()), [1]);
+        assert_eq!(claim(CLAIM_LEASE), [1]);
+        // Another worker finds nothing due while the lease runs.
+        assert!(claim(CLAIM_LEASE).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn last_attempt_fails_for_good() {
+        let (url, _received) = stub(vec![503]).await;
+        let delivery = worker(1).attempt(&webhook(url), delivery()).await;
+        assert_eq!(delivery.status, "failed");
+        assert_eq!(delivery.attempts, 1);
+        assert!(delivery.last_error.unwrap().starts_with("503"));
+    }
+}